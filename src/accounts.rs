@@ -0,0 +1,167 @@
+use crate::money::round_cents;
+use crate::plan::Plan;
+use crate::{trading_unit, Portfolio, Stock};
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Account with its holdings and the cash available for purchases
+#[derive(Clone, Debug, Default)]
+pub struct Account {
+    pub name: String,
+    /// Cash in the base currency
+    pub cash: f64,
+    /// Shares by WKN
+    pub holdings: HashMap<String, f64>,
+}
+
+impl Account {
+    pub fn new(name: &str, portfolio: &Portfolio) -> Self {
+        Account {
+            name: name.to_owned(),
+            cash: 0.0,
+            holdings: portfolio
+                .Stocks
+                .iter()
+                .map(|s| (s.WKN.clone(), s.Shares))
+                .collect(),
+        }
+    }
+}
+
+/// Order of a stock placed in one account
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct Placement {
+    pub Account: String,
+    pub WKN: String,
+    pub Shares: f64,
+    /// Order value before fees, negative for sales
+    pub Amount: f64,
+}
+
+/// Rank of `account` in the `Location` preferences of `stock`, accounts
+/// without a preference ranking last
+fn location_rank(stock: &Stock, account: &str) -> usize {
+    stock
+        .Location
+        .iter()
+        .position(|location| location == account)
+        .unwrap_or(stock.Location.len())
+}
+
+/// Splits the orders of `plan` across `accounts`. Sales are taken from the
+/// least preferred accounts holding the stock first, and their proceeds are
+/// available for purchases in the same account. Purchases of stocks with
+/// location preferences are placed first, each in the most preferred account
+/// with cash, then in the account with the most cash. Shares which fit into
+/// no account are placed in the account with the most cash left, with a
+/// warning.
+pub fn place_orders(
+    portfolio: &Portfolio,
+    plan: &Plan,
+    accounts: &[Account],
+    fractional: bool,
+) -> Vec<Placement> {
+    let mut accounts = accounts.to_vec();
+    let mut placements = Vec::new();
+    let orders = portfolio.Stocks.iter().filter_map(|stock| {
+        plan.Stocks
+            .iter()
+            .find(|e| e.WKN == stock.WKN && e.NewShares != 0.0)
+            .map(|e| (stock, e.NewShares))
+    });
+    let (sales, purchases): (Vec<_>, Vec<_>) = orders.partition(|(_, shares)| *shares < 0.0);
+
+    for (stock, shares) in sales {
+        let mut left = -shares;
+        for account in accounts
+            .iter_mut()
+            .sorted_by_key(|a| std::cmp::Reverse(location_rank(stock, &a.name)))
+        {
+            let held = account.holdings.get(&stock.WKN).copied().unwrap_or(0.0);
+            let sold = left.min(held);
+            if sold <= 0.0 {
+                continue;
+            }
+            left -= sold;
+            account.cash += sold * stock.Price;
+            placements.push(Placement {
+                Account: account.name.clone(),
+                WKN: stock.WKN.clone(),
+                Shares: -sold,
+                Amount: round_cents(-sold * stock.Price),
+            });
+        }
+        if left > 0.0 {
+            log::warn!(
+                "Accounts hold {left} shares of {} less than sold",
+                stock.WKN
+            );
+        }
+    }
+
+    for (stock, shares) in purchases
+        .into_iter()
+        .sorted_by(|a, b| (b.1 * b.0.Price).total_cmp(&(a.1 * a.0.Price)))
+        .sorted_by_key(|(stock, _)| stock.Location.is_empty())
+    {
+        let unit = trading_unit(stock, fractional);
+        let mut left = shares;
+        let order = (0..accounts.len())
+            .sorted_by(|&a, &b| {
+                location_rank(stock, &accounts[a].name)
+                    .cmp(&location_rank(stock, &accounts[b].name))
+                    .then(accounts[b].cash.total_cmp(&accounts[a].cash))
+            })
+            .collect_vec();
+        for idx in order {
+            let account = &mut accounts[idx];
+            let affordable = match unit {
+                Some(unit) => {
+                    unit.times((account.cash / stock.Price / unit.shares() + 1e-9).floor())
+                }
+                None => account.cash / stock.Price,
+            };
+            let bought = left.min(affordable);
+            if bought <= 0.0 {
+                continue;
+            }
+            left -= bought;
+            account.cash -= bought * stock.Price;
+            placements.push(Placement {
+                Account: account.name.clone(),
+                WKN: stock.WKN.clone(),
+                Shares: bought,
+                Amount: round_cents(bought * stock.Price),
+            });
+        }
+        if left > 1e-9 {
+            let Some(account) = accounts.iter_mut().max_by(|a, b| a.cash.total_cmp(&b.cash)) else {
+                continue;
+            };
+            log::warn!(
+                "{left} shares of {} exceed the cash of every account, placed in {}",
+                stock.WKN,
+                account.name
+            );
+            account.cash -= left * stock.Price;
+            match placements
+                .iter_mut()
+                .find(|p| p.Account == account.name && p.WKN == stock.WKN)
+            {
+                Some(placement) => {
+                    placement.Shares += left;
+                    placement.Amount = round_cents(placement.Shares * stock.Price);
+                }
+                None => placements.push(Placement {
+                    Account: account.name.clone(),
+                    WKN: stock.WKN.clone(),
+                    Shares: left,
+                    Amount: round_cents(left * stock.Price),
+                }),
+            }
+        }
+    }
+    placements
+}
@@ -0,0 +1,223 @@
+use crate::fx::{self, FxRates};
+use crate::quotes::{self, Bar, Dividend, PriceProvider, Quote};
+use crate::Error;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CacheEntry {
+    quote: Quote,
+    /// Unix timestamp in seconds of the fetch
+    fetched: u64,
+}
+
+/// Price provider answering from a JSON file in the user cache directory while
+/// the quotes are younger than the TTL.
+///
+/// In offline mode or if fetching fails, cached quotes of any age are used and
+/// missing ones are not fetched. Their `updated` timestamp falls back to the
+/// time of the fetch.
+pub struct CachedProvider {
+    inner: Box<dyn PriceProvider>,
+    path: PathBuf,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl CachedProvider {
+    pub fn new(inner: Box<dyn PriceProvider>, ttl: Duration) -> Result<Self, Error> {
+        let slug: String = inner
+            .name()
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .collect();
+        let path = cache_file(&format!("quotes-{}.json", slug.to_lowercase()))?;
+        Ok(CachedProvider {
+            entries: Mutex::new(read_cache_file(&path)),
+            inner,
+            path,
+            ttl,
+        })
+    }
+
+    fn cached(&self, key: &str) -> Option<Quote> {
+        let fetched = self.entries.lock().unwrap().get(key)?.fetched;
+        match quotes::is_offline() || now().saturating_sub(fetched) < self.ttl.as_secs() {
+            true => {
+                log::debug!("Using cached {} quote for {key}", self.inner.name());
+                self.outdated(key)
+            }
+            false => None,
+        }
+    }
+
+    /// Cached quote regardless of its age, with `updated` falling back to the
+    /// time of the fetch
+    fn outdated(&self, key: &str) -> Option<Quote> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(key)?;
+        Some(Quote {
+            updated: entry.quote.updated.or(Some(entry.fetched)),
+            ..entry.quote.clone()
+        })
+    }
+
+    fn store(&self, quotes: impl IntoIterator<Item = (String, Quote)>) -> Result<(), Error> {
+        let fetched = now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.extend(
+            quotes
+                .into_iter()
+                .map(|(key, quote)| (key, CacheEntry { quote, fetched })),
+        );
+        write_cache_file(&self.path, &*entries)
+    }
+}
+
+impl PriceProvider for CachedProvider {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn fetch(&self, symbol: &str) -> Result<Quote, Error> {
+        self.fetch_batch(&[symbol])?.remove(symbol).ok_or_else(|| {
+            simple_error::simple_error!("No {} quote for '{}'", self.name(), symbol).into()
+        })
+    }
+
+    fn fetch_history(&self, symbol: &str, from: &str, to: &str) -> Result<Vec<Bar>, Error> {
+        self.inner.fetch_history(symbol, from, to)
+    }
+
+    fn fetch_dividends(&self, symbol: &str, from: &str) -> Result<Vec<Dividend>, Error> {
+        self.inner.fetch_dividends(symbol, from)
+    }
+
+    fn fetch_market_cap(&self, symbol: &str) -> Result<f64, Error> {
+        self.inner.fetch_market_cap(symbol)
+    }
+
+    fn fetch_by_isin(&self, isin: &str) -> Result<Option<Quote>, Error> {
+        let key = format!("isin:{isin}");
+        if let Some(quote) = self.cached(&key) {
+            return Ok(Some(quote));
+        }
+        if quotes::is_offline() {
+            return Ok(None);
+        }
+        let quote = self.inner.fetch_by_isin(isin)?;
+        if let Some(quote) = &quote {
+            self.store([(key, quote.clone())])?;
+        }
+        Ok(quote)
+    }
+
+    fn fetch_batch(&self, symbols: &[&str]) -> Result<HashMap<String, Quote>, Error> {
+        let mut quotes: HashMap<String, Quote> = symbols
+            .iter()
+            .filter_map(|symbol| Some((symbol.to_string(), self.cached(symbol)?)))
+            .collect();
+        let missing: Vec<&str> = symbols
+            .iter()
+            .copied()
+            .filter(|symbol| !quotes.contains_key(*symbol))
+            .collect();
+        if !missing.is_empty() && !quotes::is_offline() {
+            match self.inner.fetch_batch(&missing) {
+                Ok(fetched) => {
+                    self.store(fetched.clone())?;
+                    quotes.extend(fetched);
+                }
+                Err(err) => log::warn!("Failed to fetch {} quotes: {err}", self.name()),
+            }
+            // Outdated quotes are still better than the prices in the file
+            for symbol in missing {
+                if quotes.contains_key(symbol) {
+                    continue;
+                }
+                if let Some(quote) = self.outdated(symbol) {
+                    log::warn!("Using outdated cached {} quote of {symbol}", self.name());
+                    quotes.insert(symbol.to_string(), quote);
+                }
+            }
+        }
+        Ok(quotes)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CachedRates {
+    rates: FxRates,
+    /// Unix timestamp in seconds of the fetch
+    fetched: u64,
+}
+
+/// ECB reference rates, cached in the user cache directory after every fetch.
+///
+/// In offline mode or if fetching fails, the cached rates of any age are used.
+pub fn ecb_rates() -> Result<FxRates, Error> {
+    let path = cache_file("ecb-rates.json")?;
+    let err = match quotes::is_offline() {
+        true => None,
+        false => match fx::fetch_ecb_rates() {
+            Ok(rates) => {
+                let cached = CachedRates {
+                    rates,
+                    fetched: now(),
+                };
+                write_cache_file(&path, &cached)?;
+                return Ok(cached.rates);
+            }
+            Err(err) => Some(err),
+        },
+    };
+    let Some(cached) = read_cache_file::<Option<CachedRates>>(&path) else {
+        return Err(err.unwrap_or_else(|| {
+            simple_error::simple_error!("No cached ECB reference rates, run once without --offline")
+                .into()
+        }));
+    };
+    match err {
+        Some(err) => log::warn!("Using cached ECB reference rates, fetching failed: {err}"),
+        None => log::debug!("Using cached ECB reference rates"),
+    }
+    Ok(cached.rates)
+}
+
+/// Path of `name` in the user cache directory
+pub(crate) fn cache_file(name: &str) -> Result<PathBuf, Error> {
+    Ok(dirs_next::cache_dir()
+        .ok_or_else(|| simple_error::simple_error!("No cache directory found"))?
+        .join("rebalancing")
+        .join(name))
+}
+
+/// Reads a JSON cache file, starting empty if it is missing or unreadable.
+pub(crate) fn read_cache_file<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match File::open(path) {
+        Ok(file) => serde_json::from_reader(file).unwrap_or_else(|err| {
+            log::warn!("Ignoring unreadable cache {}: {err}", path.display());
+            T::default()
+        }),
+        Err(_) => T::default(),
+    }
+}
+
+pub(crate) fn write_cache_file<T: Serialize>(path: &Path, value: &T) -> Result<(), Error> {
+    if let Some(directory) = path.parent() {
+        fs::create_dir_all(directory)?;
+    }
+    serde_json::to_writer(File::create(path)?, value)?;
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
@@ -0,0 +1,110 @@
+use crate::Error;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::PathBuf;
+use std::process::Command;
+
+const KEYRING_SERVICE: &str = "rebalancing";
+
+/// API key of a quote provider.
+///
+/// Keys are looked up in the environment, in `api_keys` of the config file
+/// `rebalancing/config.json` in the user config directory and in the OS
+/// keyring, in this order. Store a key in the keyring under service
+/// `rebalancing` and the account of the provider, e.g. on Linux with
+/// `secret-tool store --label=rebalancing service rebalancing account finnhub`
+/// or on macOS with `security add-generic-password -s rebalancing -a finnhub -w`.
+pub struct Credential {
+    /// Name of the provider for messages
+    pub name: &'static str,
+    /// Key in the config file and account in the keyring
+    pub account: &'static str,
+    pub env_var: &'static str,
+}
+
+pub const ALPHA_VANTAGE: Credential = Credential {
+    name: "Alpha Vantage",
+    account: "alpha-vantage",
+    env_var: "ALPHA_VANTAGE_API_KEY",
+};
+
+pub const FINNHUB: Credential = Credential {
+    name: "Finnhub",
+    account: "finnhub",
+    env_var: "FINNHUB_API_KEY",
+};
+
+pub const OPENFIGI: Credential = Credential {
+    name: "OpenFIGI",
+    account: "openfigi",
+    env_var: "OPENFIGI_API_KEY",
+};
+
+#[derive(Default, Deserialize)]
+struct Config {
+    #[serde(default)]
+    api_keys: HashMap<String, String>,
+}
+
+/// Path of the config file in the user config directory
+pub fn config_file() -> Option<PathBuf> {
+    Some(
+        dirs_next::config_dir()?
+            .join("rebalancing")
+            .join("config.json"),
+    )
+}
+
+impl Credential {
+    /// The API key, if configured anywhere.
+    pub fn lookup(&self) -> Result<Option<String>, Error> {
+        if let Ok(key) = std::env::var(self.env_var) {
+            return Ok(Some(key));
+        }
+        if let Some(path) = config_file().filter(|path| path.exists()) {
+            let config: Config = serde_json::from_reader(File::open(&path)?).map_err(|err| {
+                simple_error::simple_error!("Invalid {}: {}", path.display(), err)
+            })?;
+            if let Some(key) = config.api_keys.get(self.account) {
+                return Ok(Some(key.clone()));
+            }
+        }
+        Ok(self.keyring_lookup())
+    }
+
+    /// The API key, failing with hints where to configure it if missing.
+    pub fn require(&self) -> Result<String, Error> {
+        self.lookup()?.ok_or_else(|| {
+            simple_error::simple_error!(
+                "{} needs an API key, set {}, add \"{}\" to api_keys in {} or store it in the keyring",
+                self.name,
+                self.env_var,
+                self.account,
+                config_file().map_or("the config file".to_owned(), |p| p.display().to_string())
+            )
+            .into()
+        })
+    }
+
+    fn keyring_lookup(&self) -> Option<String> {
+        let mut command = match cfg!(target_os = "macos") {
+            true => {
+                let mut command = Command::new("security");
+                command.args(["find-generic-password", "-s", KEYRING_SERVICE]);
+                command.args(["-a", self.account, "-w"]);
+                command
+            }
+            false => {
+                let mut command = Command::new("secret-tool");
+                command.args(["lookup", "service", KEYRING_SERVICE]);
+                command.args(["account", self.account]);
+                command
+            }
+        };
+        // A missing keyring tool is the same as a missing key
+        let output = command.output().ok().filter(|o| o.status.success())?;
+        let key = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+        (!key.is_empty()).then_some(key)
+    }
+}
@@ -0,0 +1,52 @@
+use crate::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Current UTC date as YYYY-MM-DD
+pub fn today() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    from_timestamp(seconds as i64)
+}
+
+/// UTC date of a Unix timestamp as YYYY-MM-DD
+pub fn from_timestamp(seconds: i64) -> String {
+    let days = seconds.div_euclid(SECONDS_PER_DAY);
+
+    // Civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Unix timestamp of midnight UTC of a YYYY-MM-DD date
+pub fn to_timestamp(date: &str) -> Result<i64, Error> {
+    let invalid = || simple_error::simple_error!("Invalid date '{}', expected YYYY-MM-DD", date);
+    let mut parts = date.splitn(3, '-').map(|part| part.parse::<i64>());
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid().into());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid().into());
+    }
+
+    // Days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Ok((era * 146097 + doe - 719468) * SECONDS_PER_DAY)
+}
@@ -0,0 +1,88 @@
+use crate::{Portfolio, Stock};
+use itertools::Itertools;
+use prettytable::{format, row, Table};
+use serde::Serialize;
+
+/// Change of a stock between two portfolio files. Stocks missing in one of
+/// the portfolios count with zero shares.
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct StockDiff {
+    pub WKN: String,
+    pub OldShares: f64,
+    pub NewShares: f64,
+    pub OldPrice: f64,
+    pub NewPrice: f64,
+    pub GoalRatio: f64,
+    pub OldRatio: f64,
+    pub NewRatio: f64,
+}
+
+pub fn diff_portfolios(old: &Portfolio, new: &Portfolio) -> Vec<StockDiff> {
+    let value = |portfolio: &Portfolio| {
+        portfolio
+            .Stocks
+            .iter()
+            .fold(0.0, |acc, elem| acc + elem.Price * elem.Shares)
+    };
+    let (old_sum, new_sum) = (value(old), value(new));
+    fn find<'a>(portfolio: &'a Portfolio, wkn: &str) -> Option<&'a Stock> {
+        portfolio.Stocks.iter().find(|s| s.WKN == wkn)
+    }
+
+    old.Stocks
+        .iter()
+        .chain(new.Stocks.iter())
+        .map(|s| s.WKN.as_str())
+        .unique()
+        .map(|wkn| {
+            let (old_stock, new_stock) = (find(old, wkn), find(new, wkn));
+            let reference = new_stock
+                .or(old_stock)
+                .expect("WKN is taken from a portfolio");
+            let shares = |stock: Option<&Stock>| stock.map_or(0.0, |s| s.Shares);
+            let price = |stock: Option<&Stock>| stock.map_or(reference.Price, |s| s.Price);
+
+            StockDiff {
+                WKN: wkn.to_owned(),
+                OldShares: shares(old_stock),
+                NewShares: shares(new_stock),
+                OldPrice: price(old_stock),
+                NewPrice: price(new_stock),
+                GoalRatio: reference.GoalRatio,
+                OldRatio: price(old_stock) * shares(old_stock) / old_sum,
+                NewRatio: price(new_stock) * shares(new_stock) / new_sum,
+            }
+        })
+        .collect()
+}
+
+pub fn print_diff(diffs: &[StockDiff]) {
+    let mut table = Table::new();
+    table.set_titles(row![
+        "WKN",
+        "Old Shares",
+        "New Shares",
+        "Old Price",
+        "New Price",
+        "Goal Ratio",
+        "Old Drift",
+        "New Drift"
+    ]);
+
+    for diff in diffs {
+        table.add_row(row![
+            diff.WKN,
+            diff.OldShares,
+            diff.NewShares,
+            diff.OldPrice,
+            diff.NewPrice,
+            format!("{:.4}", diff.GoalRatio),
+            format!("{:+.4}", diff.OldRatio - diff.GoalRatio),
+            format!("{:+.4}", diff.NewRatio - diff.GoalRatio),
+        ]);
+    }
+    table.set_format(*format::consts::FORMAT_NO_BORDER);
+
+    println!("\n{table}");
+}
@@ -0,0 +1,77 @@
+use crate::credentials::config_file;
+use crate::Error;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+
+/// Fees charged by a broker for every order
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct FeeModel {
+    /// Fixed fee per order
+    pub fixed: f64,
+    /// Fee as fraction of the order value
+    pub rate: f64,
+}
+
+#[derive(Default, Deserialize)]
+struct Config {
+    #[serde(default)]
+    fees: HashMap<String, FeeModel>,
+}
+
+impl FeeModel {
+    /// Fee of an order of `order_value`, negative for sales. Not placing an
+    /// order is free.
+    pub fn fee(&self, order_value: f64) -> f64 {
+        match order_value == 0.0 {
+            true => 0.0,
+            false => self.fixed + self.rate * order_value.abs(),
+        }
+    }
+
+    pub fn is_free(&self) -> bool {
+        self.fixed == 0.0 && self.rate == 0.0
+    }
+
+    /// Smallest order value whose fee is at most `max_ratio` of it, infinite
+    /// if the rate alone exceeds it
+    pub fn min_efficient_order(&self, max_ratio: f64) -> f64 {
+        match max_ratio > self.rate {
+            true => self.fixed / (max_ratio - self.rate),
+            false => f64::INFINITY,
+        }
+    }
+
+    /// Parses `FIXED`, `RATE%` or `FIXED+RATE%`, e.g. `4.90+0.25%`, or looks
+    /// up a broker in `fees` of the config file.
+    pub fn from_spec(spec: &str) -> Result<Self, Error> {
+        let parse = |part: &str| -> Option<FeeModel> {
+            let mut fees = FeeModel::default();
+            for term in part.split('+').map(str::trim) {
+                match term.strip_suffix('%') {
+                    Some(percent) => fees.rate += percent.trim().parse::<f64>().ok()? / 100.0,
+                    None => fees.fixed += term.parse::<f64>().ok()?,
+                }
+            }
+            Some(fees)
+        };
+        if let Some(fees) = parse(spec) {
+            return Ok(fees);
+        }
+
+        let path = config_file()
+            .filter(|path| path.exists())
+            .ok_or_else(|| simple_error::simple_error!("Invalid fees '{}'", spec))?;
+        let config: Config = serde_json::from_reader(File::open(&path)?)
+            .map_err(|err| simple_error::simple_error!("Invalid {}: {}", path.display(), err))?;
+        config.fees.get(spec).copied().ok_or_else(|| {
+            simple_error::simple_error!(
+                "Invalid fees '{}', neither a fee like `4.90+0.25%` nor a broker in fees of {}",
+                spec,
+                path.display()
+            )
+            .into()
+        })
+    }
+}
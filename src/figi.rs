@@ -0,0 +1,98 @@
+use crate::cache::{cache_file, read_cache_file, write_cache_file};
+use crate::quotes::{self, post_json};
+use crate::{credentials, Error, Portfolio};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+const OPENFIGI_MAPPING_URL: &str = "https://api.openfigi.com/v3/mapping";
+
+/// Mapping jobs per request allowed without API key
+const JOBS_PER_REQUEST: usize = 10;
+
+/// Yahoo Finance symbol suffixes of Bloomberg exchange codes, in order of
+/// preference
+const EXCHANGE_SUFFIXES: &[(&str, &str)] = &[
+    ("US", ""),
+    ("GY", ".DE"),
+    ("GR", ".F"),
+    ("NA", ".AS"),
+    ("LN", ".L"),
+    ("SW", ".SW"),
+    ("FP", ".PA"),
+    ("IM", ".MI"),
+];
+
+/// Fills empty `Symbol`s with tickers resolved by ISIN, or WKN if there is no
+/// ISIN, with OpenFIGI.
+///
+/// Resolved symbols are cached in the user cache directory, including those
+/// resolved before a failed request. Stocks without a listing on a known
+/// exchange keep their empty symbol.
+pub fn resolve_symbols(portfolio: &mut Portfolio) -> Result<(), Error> {
+    let path = cache_file("symbols.json")?;
+    let mut symbols: HashMap<String, String> = read_cache_file(&path);
+
+    let identifier = |isin: &str, wkn: &str| match isin.is_empty() {
+        true => ("ID_WERTPAPIER", wkn.to_owned()),
+        false => ("ID_ISIN", isin.to_owned()),
+    };
+    let missing: Vec<(&str, String)> = portfolio
+        .Stocks
+        .iter()
+        .filter(|s| s.Symbol.is_empty())
+        .map(|s| identifier(&s.ISIN, &s.WKN))
+        .filter(|(_, id)| !id.is_empty() && !symbols.contains_key(id))
+        .collect();
+
+    let mut failure = None;
+    if !missing.is_empty() && !quotes::is_offline() {
+        // The API key is optional and only raises the rate limit
+        let headers: Vec<String> = credentials::OPENFIGI
+            .lookup()?
+            .map(|key| format!("X-OPENFIGI-APIKEY: {key}"))
+            .into_iter()
+            .collect();
+        for chunk in missing.chunks(JOBS_PER_REQUEST) {
+            let jobs: Vec<Value> = chunk
+                .iter()
+                .map(|(id_type, id)| json!({ "idType": id_type, "idValue": id }))
+                .collect();
+            let response = match post_json(OPENFIGI_MAPPING_URL, &Value::from(jobs), &headers) {
+                Ok(response) => response,
+                Err(err) => {
+                    failure = Some(err);
+                    break;
+                }
+            };
+            for ((_, id), result) in chunk.iter().zip(response.as_array().into_iter().flatten()) {
+                match yahoo_symbol(result) {
+                    Some(symbol) => {
+                        log::info!("Resolved {id} to symbol {symbol}");
+                        symbols.insert(id.clone(), symbol);
+                    }
+                    None => log::warn!("No listing of {id} found on OpenFIGI"),
+                }
+            }
+        }
+        write_cache_file(&path, &symbols)?;
+    }
+
+    for stock in portfolio.Stocks.iter_mut().filter(|s| s.Symbol.is_empty()) {
+        if let Some(symbol) = symbols.get(&identifier(&stock.ISIN, &stock.WKN).1) {
+            stock.Symbol = symbol.clone();
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
+
+/// Ticker with Yahoo Finance suffix of the listing on the most preferred
+/// exchange in a mapping result.
+fn yahoo_symbol(result: &Value) -> Option<String> {
+    let listings = result["data"].as_array()?;
+    EXCHANGE_SUFFIXES.iter().find_map(|(exchange, suffix)| {
+        let listing = listings
+            .iter()
+            .find(|l| l["exchCode"].as_str() == Some(exchange))?;
+        Some(format!("{}{suffix}", listing["ticker"].as_str()?))
+    })
+}
@@ -0,0 +1,123 @@
+use crate::{quotes, Error, Portfolio};
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+
+const ECB_DAILY_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml";
+
+/// Units of a currency per EUR by ISO code
+pub type FxRates = HashMap<String, f64>;
+
+/// Fetches the daily ECB reference rates.
+pub fn fetch_ecb_rates() -> Result<FxRates, Error> {
+    parse_ecb_rates(&quotes::fetch_text(ECB_DAILY_URL)?)
+}
+
+/// Parses the `<Cube currency='USD' rate='1.0845'/>` entries of the ECB
+/// reference rate XML.
+pub fn parse_ecb_rates(xml: &str) -> Result<FxRates, Error> {
+    let cube = Regex::new(r#"currency=['"]([A-Z]{3})['"]\s+rate=['"]([0-9.]+)['"]"#)?;
+    let mut rates: FxRates = cube
+        .captures_iter(xml)
+        .map(|c| Ok((c[1].to_owned(), c[2].parse()?)))
+        .collect::<Result<_, Error>>()?;
+    if rates.is_empty() {
+        simple_error::bail!("No rates found in ECB reference rates");
+    }
+    rates.insert("EUR".to_owned(), 1.0);
+    Ok(rates)
+}
+
+/// `amount` in `currency` converted into `base`, unchanged without a
+/// `currency`
+pub fn convert(amount: f64, currency: &str, base: &str, rates: &FxRates) -> Result<f64, Error> {
+    if currency.is_empty() || currency == base {
+        return Ok(amount);
+    }
+    let rate = |currency: &str| {
+        rates.get(currency).copied().ok_or_else(|| {
+            Error::from(simple_error::simple_error!(
+                "No exchange rate for {}",
+                currency
+            ))
+        })
+    };
+    Ok(amount / rate(currency)? * rate(base)?)
+}
+
+/// Converts the prices of all stocks with a `Currency` other than `base` and
+/// the cash balances into `base`.
+pub fn convert_to_base(
+    portfolio: &mut Portfolio,
+    base: &str,
+    rates: &FxRates,
+) -> Result<(), Error> {
+    for stock in portfolio.Stocks.iter_mut() {
+        if stock.Currency.is_empty() || stock.Currency == base {
+            continue;
+        }
+        let price = convert(stock.Price, &stock.Currency, base, rates)?;
+        log::info!(
+            "Converted price of {} from {} {} to {:.4} {}",
+            stock.WKN,
+            stock.Price,
+            stock.Currency,
+            price,
+            base
+        );
+        for lot in stock.Lots.iter_mut() {
+            lot.Price *= price / stock.Price;
+        }
+        stock.Price = price;
+        stock.Currency = base.to_owned();
+    }
+
+    let mut cash = BTreeMap::new();
+    for (currency, amount) in std::mem::take(&mut portfolio.Cash) {
+        let converted = convert(amount, &currency, base, rates)?;
+        if currency != base {
+            log::info!("Converted cash of {amount} {currency} to {converted:.2} {base}");
+        }
+        *cash.entry(base.to_owned()).or_default() += converted;
+    }
+    portfolio.Cash = cash;
+    Ok(())
+}
+
+/// Takes `amount` in `base` from the cash balances of the unconverted
+/// `portfolio`, first from the balance in `base` and then from the other
+/// balances converted at `rates`. A negative `amount` is added to the balance
+/// in `base`.
+pub fn withdraw_in_base(
+    portfolio: &mut Portfolio,
+    base: &str,
+    amount: f64,
+    rates: &FxRates,
+) -> Result<(), Error> {
+    let mut remaining = amount;
+    let base_balance = portfolio.Cash.get(base).copied().unwrap_or_default();
+    if remaining > 0.0 && base_balance > 0.0 {
+        let taken = remaining.min(base_balance);
+        portfolio.withdraw_cash(base, taken);
+        remaining -= taken;
+    }
+    let others: Vec<(String, f64)> = portfolio
+        .Cash
+        .iter()
+        .filter(|(currency, balance)| *currency != base && **balance > 0.0)
+        .map(|(currency, balance)| (currency.clone(), *balance))
+        .collect();
+    for (currency, balance) in others {
+        if remaining <= 0.0 {
+            break;
+        }
+        // Units of `currency` per unit of `base`
+        let rate = convert(1.0, base, &currency, rates)?;
+        let taken = remaining.min(balance / rate);
+        portfolio.withdraw_cash(&currency, (taken * rate).min(balance));
+        remaining -= taken;
+    }
+    if remaining != 0.0 {
+        portfolio.withdraw_cash(base, remaining);
+    }
+    Ok(())
+}
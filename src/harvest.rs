@@ -0,0 +1,68 @@
+use crate::plan::Plan;
+use crate::{Portfolio, Stock};
+use itertools::Itertools;
+use serde::Serialize;
+
+/// Sale realizing the unrealized loss of the oldest lots of a stock, to be
+/// replaced by buying its `Alternative`.
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct Harvest {
+    pub WKN: String,
+    /// Shares to sell, covering the oldest lots up to the largest loss
+    pub Shares: f64,
+    pub Loss: f64,
+    /// Tax saved by offsetting the loss against gains
+    pub TaxSaving: f64,
+    /// Similar security to buy with the proceeds, keeping the allocation
+    pub Alternative: Option<String>,
+}
+
+/// Suggests sales of stocks with a harvestable loss of at least `min_loss`,
+/// largest loss first. Stocks sold by the plan are skipped since their sale
+/// realizes gains or losses anyway.
+pub fn harvest_losses(
+    portfolio: &Portfolio,
+    plan: &Plan,
+    min_loss: f64,
+    tax_rate: f64,
+) -> Vec<Harvest> {
+    let sold_by_plan = |stock: &Stock| {
+        plan.Stocks
+            .iter()
+            .any(|e| e.WKN == stock.WKN && e.NewShares < 0.0)
+    };
+
+    portfolio
+        .Stocks
+        .iter()
+        .filter(|stock| !sold_by_plan(stock))
+        .filter_map(|stock| {
+            let (shares, loss) = largest_loss(stock);
+            (loss >= min_loss && loss > 0.0).then(|| Harvest {
+                WKN: stock.WKN.clone(),
+                Shares: shares,
+                Loss: loss,
+                TaxSaving: loss * tax_rate,
+                Alternative: stock.Alternative.clone(),
+            })
+        })
+        .sorted_by(|a, b| b.Loss.total_cmp(&a.Loss))
+        .collect()
+}
+
+/// Shares to sell first in first out for the largest realized loss, and the
+/// loss.
+fn largest_loss(stock: &Stock) -> (f64, f64) {
+    let mut best = (0.0, 0.0);
+    let (mut shares, mut gain) = (0.0, 0.0);
+    for lot in stock.Lots.iter().sorted_by(|a, b| a.Date.cmp(&b.Date)) {
+        let sold = lot.Shares.min(stock.Shares - shares);
+        shares += sold;
+        gain += sold * (stock.Price - lot.Price);
+        if -gain > best.1 {
+            best = (shares, -gain);
+        }
+    }
+    best
+}
@@ -0,0 +1,538 @@
+use crate::{Error, Lot, Portfolio, Stock};
+use itertools::Itertools;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::Read;
+
+/// Goal ratios keyed by ISIN, WKN or symbol, used for broker exports which
+/// only contain holdings.
+pub type GoalRatios = HashMap<String, f64>;
+
+pub fn read_goal_ratios<R: Read>(reader: R) -> Result<GoalRatios, Error> {
+    Ok(serde_json::from_reader(reader)?)
+}
+
+pub fn read_dkb<R: Read>(reader: R, goal_ratios: &GoalRatios) -> Result<Portfolio, Error> {
+    let table = ExportTable::read(reader, "DKB", "\"Bestand\"", b';')?;
+    let (shares_col, id_col, price_col) = (
+        table.column("Bestand")?,
+        table.column("ISIN / WKN")?,
+        table.column("Kurs")?,
+    );
+
+    let mut stocks = Vec::new();
+    for record in table.records.iter() {
+        // Skip empty and summary rows
+        let Some((isin, wkn)) = record.get(id_col).and_then(|id| id.split_once(" / ")) else {
+            continue;
+        };
+        let (isin, wkn) = (isin.trim(), wkn.trim());
+
+        stocks.push(Stock {
+            WKN: wkn.to_owned(),
+            ISIN: isin.to_owned(),
+            Price: parse_german_number(record.get(price_col).unwrap_or_default())?,
+            Shares: parse_german_number(record.get(shares_col).unwrap_or_default())?,
+            GoalRatio: lookup_goal_ratio(goal_ratios, &[isin, wkn])?,
+            Symbol: String::new(),
+            ..Default::default()
+        });
+    }
+
+    Ok(Portfolio {
+        Stocks: stocks,
+        ..Default::default()
+    })
+}
+
+pub fn read_comdirect<R: Read>(reader: R, goal_ratios: &GoalRatios) -> Result<Portfolio, Error> {
+    let table = ExportTable::read(reader, "comdirect", "\"WKN\"", b';')?;
+    let (wkn_col, shares_col, price_col) = (
+        table.column("WKN")?,
+        table.column("Stück/Nom.")?,
+        table.column("Akt. Kurs")?,
+    );
+    // Only some comdirect export variants include the ISIN
+    let isin_col = table.column("ISIN").ok();
+
+    let mut stocks = Vec::new();
+    for record in table.records.iter() {
+        let wkn = record.get(wkn_col).unwrap_or_default().trim();
+        if wkn.is_empty() {
+            continue;
+        }
+        let isin = isin_col
+            .and_then(|col| record.get(col))
+            .unwrap_or_default()
+            .trim();
+
+        stocks.push(Stock {
+            WKN: wkn.to_owned(),
+            ISIN: isin.to_owned(),
+            Price: parse_german_number(record.get(price_col).unwrap_or_default())?,
+            Shares: parse_german_number(record.get(shares_col).unwrap_or_default())?,
+            GoalRatio: lookup_goal_ratio(goal_ratios, &[isin, wkn])?,
+            Symbol: String::new(),
+            ..Default::default()
+        });
+    }
+
+    Ok(Portfolio {
+        Stocks: stocks,
+        ..Default::default()
+    })
+}
+
+/// Shares and EUR value columns of Degiro exports by language, and whether
+/// the language writes numbers with a decimal comma
+const DEGIRO_LOCALES: &[(&str, &str, bool)] = &[
+    ("Amount", "Value in EUR", false),
+    ("Anzahl", "Wert in EUR", true),
+    ("Aantal", "Waarde in EUR", true),
+];
+
+/// Reads a Degiro `Portfolio.csv` export in English, German or Dutch.
+///
+/// Degiro exports carry no WKN, so the ISIN is used as identifier. Prices are
+/// derived from the EUR value to account for positions in other currencies.
+/// The language of the column names decides the decimal separator.
+pub fn read_degiro<R: Read>(reader: R, goal_ratios: &GoalRatios) -> Result<Portfolio, Error> {
+    let table = ExportTable::read(reader, "Degiro", "Symbol/ISIN", b',')?;
+    let isin_col = table.column("Symbol/ISIN")?;
+    let Some((shares_col, value_col, decimal_comma)) =
+        DEGIRO_LOCALES
+            .iter()
+            .find_map(|&(shares, value, decimal_comma)| {
+                Some((
+                    table.column(shares).ok()?,
+                    table.column(value).ok()?,
+                    decimal_comma,
+                ))
+            })
+    else {
+        simple_error::bail!("Columns of shares and value in EUR missing in Degiro export");
+    };
+
+    let mut stocks = Vec::new();
+    for record in table.records.iter() {
+        let isin = record.get(isin_col).unwrap_or_default().trim();
+        // Skip cash positions
+        if isin.is_empty() {
+            continue;
+        }
+        let shares = parse_decimal(record.get(shares_col).unwrap_or_default(), decimal_comma)?;
+        let value = parse_decimal(record.get(value_col).unwrap_or_default(), decimal_comma)?;
+        // Closed positions have no price to derive
+        if shares == 0.0 {
+            log::debug!("Skipping position {isin} without shares in Degiro export");
+            continue;
+        }
+
+        stocks.push(Stock {
+            WKN: isin.to_owned(),
+            ISIN: isin.to_owned(),
+            Price: value / shares,
+            Shares: shares,
+            GoalRatio: lookup_goal_ratio(goal_ratios, &[isin])?,
+            Symbol: String::new(),
+            ..Default::default()
+        });
+    }
+
+    Ok(Portfolio {
+        Stocks: stocks,
+        ..Default::default()
+    })
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum UsBroker {
+    Fidelity,
+    Schwab,
+    Vanguard,
+}
+
+impl UsBroker {
+    /// Name of the broker and the symbol, quantity and price columns in its
+    /// position export.
+    fn columns(&self) -> (&'static str, [&'static str; 3]) {
+        match self {
+            UsBroker::Fidelity => ("Fidelity", ["Symbol", "Quantity", "Last Price"]),
+            UsBroker::Schwab => ("Schwab", ["Symbol", "Quantity", "Price"]),
+            UsBroker::Vanguard => ("Vanguard", ["Symbol", "Shares", "Share Price"]),
+        }
+    }
+}
+
+/// Reads a position export of a US broker.
+///
+/// These exports carry no WKN, so the symbol is used as identifier. Rows
+/// without a numeric quantity or price, e.g. cash, totals and disclaimers,
+/// are skipped.
+pub fn read_us_broker<R: Read>(
+    reader: R,
+    broker: UsBroker,
+    goal_ratios: &GoalRatios,
+) -> Result<Portfolio, Error> {
+    let (source, [symbol_header, shares_header, price_header]) = broker.columns();
+    let table = ExportTable::read(reader, source, symbol_header, b',')?;
+    let (symbol_col, shares_col, price_col) = (
+        table.column(symbol_header)?,
+        table.column(shares_header)?,
+        table.column(price_header)?,
+    );
+    let parse_dollars = |value: Option<&str>| {
+        value
+            .unwrap_or_default()
+            .replace(['$', ','], "")
+            .parse::<f64>()
+    };
+
+    let mut stocks = Vec::new();
+    for record in table.records.iter() {
+        let symbol = record.get(symbol_col).unwrap_or_default().trim();
+        let (Ok(shares), Ok(price)) = (
+            parse_dollars(record.get(shares_col)),
+            parse_dollars(record.get(price_col)),
+        ) else {
+            log::debug!("Skipping non-position row {symbol:?} in {source} export");
+            continue;
+        };
+
+        stocks.push(Stock {
+            WKN: symbol.to_owned(),
+            ISIN: String::new(),
+            Price: price,
+            Shares: shares,
+            GoalRatio: lookup_goal_ratio(goal_ratios, &[symbol])?,
+            Symbol: symbol.to_owned(),
+            ..Default::default()
+        });
+    }
+
+    Ok(Portfolio {
+        Stocks: stocks,
+        ..Default::default()
+    })
+}
+
+/// Reads open positions from an Interactive Brokers Flex Query XML report.
+///
+/// Flex reports carry no WKN, so the symbol is used as identifier. Prices are
+/// converted to the account base currency with the reported FX rate.
+pub fn read_ibkr_flex<R: Read>(
+    mut reader: R,
+    goal_ratios: &GoalRatios,
+) -> Result<Portfolio, Error> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+
+    let position_regex = Regex::new(r"<OpenPosition\s([^>]*)>")?;
+    let attribute_regex = Regex::new(r#"(\w+)="([^"]*)""#)?;
+
+    let mut stocks = Vec::new();
+    for position in position_regex.captures_iter(&content) {
+        let attributes: HashMap<&str, &str> = attribute_regex
+            .captures_iter(position.get(1).map_or("", |m| m.as_str()))
+            .filter_map(|c| Some((c.get(1)?.as_str(), c.get(2)?.as_str())))
+            .collect();
+        // Lot-level rows repeat the summary position
+        if attributes.get("levelOfDetail") == Some(&"LOT") {
+            continue;
+        }
+
+        let attribute = |name: &str| {
+            attributes.get(name).copied().ok_or::<Error>(
+                simple_error::simple_error!("Attribute '{}' missing in OpenPosition", name).into(),
+            )
+        };
+        let parse_attribute = |name: &str| -> Result<f64, Error> {
+            let value = attribute(name)?;
+            value.parse().map_err(|_| {
+                simple_error::simple_error!("Invalid number '{}' for {}", value, name).into()
+            })
+        };
+
+        let symbol = attribute("symbol")?;
+        let isin = attributes.get("isin").copied().unwrap_or_default();
+        let conid = attributes.get("conid").copied().unwrap_or_default();
+        let fx_rate = match attributes.contains_key("fxRateToBase") {
+            true => parse_attribute("fxRateToBase")?,
+            false => 1.0,
+        };
+
+        stocks.push(Stock {
+            WKN: symbol.to_owned(),
+            ISIN: isin.to_owned(),
+            Price: parse_attribute("markPrice")? * fx_rate,
+            Shares: parse_attribute("position")?,
+            GoalRatio: lookup_goal_ratio(goal_ratios, &[conid, isin, symbol])?,
+            Symbol: symbol.to_owned(),
+            ..Default::default()
+        });
+    }
+
+    Ok(Portfolio {
+        Stocks: stocks,
+        ..Default::default()
+    })
+}
+
+#[derive(Deserialize)]
+struct GhostfolioExport {
+    activities: Vec<GhostfolioActivity>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GhostfolioActivity {
+    #[serde(rename = "type")]
+    activity_type: String,
+    date: String,
+    symbol: String,
+    quantity: f64,
+    unit_price: f64,
+}
+
+/// Reads a Ghostfolio account export and reduces its activities to holdings.
+///
+/// The export carries no market data, so the unit price of the latest buy or
+/// sell of each symbol is used as its price.
+pub fn read_ghostfolio<R: Read>(reader: R, goal_ratios: &GoalRatios) -> Result<Portfolio, Error> {
+    let mut export: GhostfolioExport = serde_json::from_reader(reader)?;
+    export.activities.sort_by(|a, b| a.date.cmp(&b.date));
+
+    let mut holdings: Vec<(String, f64, f64)> = Vec::new();
+    for activity in export.activities.iter() {
+        let quantity = match activity.activity_type.as_str() {
+            "BUY" => activity.quantity,
+            "SELL" => -activity.quantity,
+            _ => continue,
+        };
+        match holdings
+            .iter_mut()
+            .find(|(symbol, ..)| *symbol == activity.symbol)
+        {
+            Some((_, shares, price)) => {
+                *shares += quantity;
+                *price = activity.unit_price;
+            }
+            None => holdings.push((activity.symbol.clone(), quantity, activity.unit_price)),
+        }
+    }
+
+    let stocks = holdings
+        .into_iter()
+        .filter(|(_, shares, _)| *shares > 0.0)
+        .map(|(symbol, shares, price)| {
+            log::info!("Using price {price} of latest activity for {symbol}");
+            Ok(Stock {
+                WKN: symbol.clone(),
+                ISIN: String::new(),
+                Price: price,
+                Shares: shares,
+                GoalRatio: lookup_goal_ratio(goal_ratios, &[&symbol])?,
+                Symbol: symbol,
+                ..Default::default()
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(Portfolio {
+        Stocks: stocks,
+        ..Default::default()
+    })
+}
+
+/// Reads holdings from a beancount or hledger journal.
+///
+/// Commodities with a price directive are treated as holdings, their shares
+/// are the sum of all postings to asset accounts and their price is the
+/// latest price directive. Purchases with a cost, `{100.50 EUR}` in beancount
+/// or `@ 100.50 EUR` and `@@ 1005 EUR` in hledger, become `Lots` dated by
+/// their transaction, and sales take shares from the oldest lots in the order
+/// of the journal. Numbers have a decimal point unless an hledger
+/// `decimal-mark ,` directive says otherwise.
+pub fn read_ledger<R: Read>(mut reader: R, goal_ratios: &GoalRatios) -> Result<Portfolio, Error> {
+    let mut content = String::new();
+    reader.read_to_string(&mut content)?;
+    let decimal_comma = content.lines().any(|line| line.trim() == "decimal-mark ,");
+
+    // beancount: `2024-01-31 price VWCE 100.50 EUR`, hledger: `P 2024-01-31 VWCE 100.50 EUR`
+    let price_regex = Regex::new(
+        r"^(?:P\s+(\d{4}-\d{2}-\d{2})|(\d{4}-\d{2}-\d{2})\s+price)\s+(\S+)\s+([\d.,]+)",
+    )?;
+    let transaction_regex = Regex::new(r"^(\d{4})[-/](\d{2})[-/](\d{2})\s")?;
+    let posting_regex = Regex::new(r"^\s+(?i:assets)\S*\s+(-?[\d.,]+)\s+([A-Za-z][\w.'-]*)(.*)")?;
+    let cost_regex = Regex::new(r"\{\s*([\d.,]+)|(@@?)\s*([\d.,]+)")?;
+
+    let mut prices: HashMap<&str, (&str, f64)> = HashMap::new();
+    let mut holdings: Vec<Stock> = Vec::new();
+    let mut date = String::new();
+    for line in content.lines() {
+        if let Some(captures) = price_regex.captures(line) {
+            let date = captures
+                .get(1)
+                .or(captures.get(2))
+                .map_or("", |m| m.as_str());
+            let commodity = captures.get(3).map_or("", |m| m.as_str());
+            let price = parse_decimal(captures.get(4).map_or("", |m| m.as_str()), decimal_comma)?;
+            if prices
+                .get(commodity)
+                .is_none_or(|(latest, _)| *latest <= date)
+            {
+                prices.insert(commodity, (date, price));
+            }
+        } else if let Some(captures) = transaction_regex.captures(line) {
+            date = format!("{}-{}-{}", &captures[1], &captures[2], &captures[3]);
+        } else if let Some(captures) = posting_regex.captures(line) {
+            let quantity =
+                parse_decimal(captures.get(1).map_or("", |m| m.as_str()), decimal_comma)?;
+            let commodity = captures.get(2).map_or("", |m| m.as_str());
+            let cost = match cost_regex.captures(captures.get(3).map_or("", |m| m.as_str())) {
+                Some(cost) => match (cost.get(1), cost.get(2), cost.get(3)) {
+                    (Some(unit), _, _) => Some(parse_decimal(unit.as_str(), decimal_comma)?),
+                    (None, Some(at), Some(amount)) => {
+                        let amount = parse_decimal(amount.as_str(), decimal_comma)?;
+                        match at.as_str() {
+                            "@@" => Some(amount / quantity.abs()),
+                            _ => Some(amount),
+                        }
+                    }
+                    _ => None,
+                },
+                None => None,
+            };
+
+            let stock = match holdings.iter().position(|s| s.WKN == commodity) {
+                Some(idx) => &mut holdings[idx],
+                None => {
+                    holdings.push(Stock {
+                        WKN: commodity.to_owned(),
+                        ..Default::default()
+                    });
+                    holdings.last_mut().expect("stock just pushed")
+                }
+            };
+            stock.Shares += quantity;
+            match (quantity > 0.0, cost) {
+                (true, Some(price)) => stock.Lots.push(Lot {
+                    Date: date.clone(),
+                    Shares: quantity,
+                    Price: price,
+                }),
+                (false, _) => stock.trade_lots(quantity, &date),
+                (true, None) => {}
+            }
+        }
+    }
+
+    let stocks = holdings
+        .into_iter()
+        .filter(|stock| stock.Shares > 0.0)
+        .filter_map(|stock| {
+            let (_, price) = prices.get(stock.WKN.as_str())?;
+            Some(Stock {
+                Price: *price,
+                ..stock
+            })
+        })
+        .map(|stock| {
+            Ok(Stock {
+                GoalRatio: lookup_goal_ratio(goal_ratios, &[&stock.WKN])?,
+                Symbol: stock.WKN.clone(),
+                ..stock
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    Ok(Portfolio {
+        Stocks: stocks,
+        ..Default::default()
+    })
+}
+
+/// Table embedded in a broker CSV export.
+struct ExportTable {
+    source: &'static str,
+    headers: csv::StringRecord,
+    records: Vec<csv::StringRecord>,
+}
+
+impl ExportTable {
+    fn read<R: Read>(
+        mut reader: R,
+        source: &'static str,
+        header_marker: &str,
+        delimiter: u8,
+    ) -> Result<Self, Error> {
+        // Exports are often Latin-1 encoded and prefix the table with account info
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        let content = String::from_utf8(content)
+            .unwrap_or_else(|e| e.into_bytes().iter().map(|&b| b as char).collect());
+        let table = content
+            .lines()
+            .skip_while(|line| !line.contains(header_marker))
+            // Some exports append further sections after a blank line
+            .take_while(|line| !line.trim().is_empty())
+            .join("\n");
+        if table.is_empty() {
+            simple_error::bail!("No holdings table in {} export", source);
+        }
+
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_reader(table.as_bytes());
+        let headers = csv_reader.headers()?.clone();
+        let records = csv_reader
+            .records()
+            .collect::<Result<Vec<_>, csv::Error>>()?;
+
+        Ok(ExportTable {
+            source,
+            headers,
+            records,
+        })
+    }
+
+    fn column(&self, name: &str) -> Result<usize, Error> {
+        self.headers
+            .iter()
+            .position(|h| h.trim() == name)
+            .ok_or_else(|| {
+                simple_error::simple_error!("Column '{}' missing in {} export", name, self.source)
+                    .into()
+            })
+    }
+}
+
+fn parse_german_number(value: &str) -> Result<f64, Error> {
+    let normalized = value.trim().replace('.', "").replace(',', ".");
+    normalized
+        .parse()
+        .map_err(|_| simple_error::simple_error!("Invalid number '{}'", value).into())
+}
+
+/// Parses a number with a decimal comma or a decimal point, the other one
+/// separating thousands, as given by the locale of the export.
+fn parse_decimal(value: &str, decimal_comma: bool) -> Result<f64, Error> {
+    match decimal_comma {
+        true => parse_german_number(value),
+        false => value
+            .trim()
+            .replace(',', "")
+            .parse()
+            .map_err(|_| simple_error::simple_error!("Invalid number '{}'", value).into()),
+    }
+}
+
+pub(crate) fn lookup_goal_ratio(goal_ratios: &GoalRatios, ids: &[&str]) -> Result<f64, Error> {
+    ids.iter()
+        .filter(|id| !id.is_empty())
+        .find_map(|id| goal_ratios.get(*id))
+        .copied()
+        .ok_or_else(|| simple_error::simple_error!("No goal ratio for {}", ids.join(" / ")).into())
+}
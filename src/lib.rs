@@ -1,119 +1,1601 @@
 use itertools::Itertools;
 use prettytable::format;
-use prettytable::{row, Table};
+use prettytable::{color, Attr, Cell, Row, Table};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub mod accounts;
+pub mod cache;
+pub mod credentials;
+pub mod date;
+pub mod diff;
+pub mod fees;
+pub mod figi;
+pub mod fx;
+pub mod harvest;
+pub mod import;
+pub mod locale;
+pub mod money;
+pub mod plan;
+pub mod quotes;
+pub mod report;
+pub mod risk;
+pub mod savings;
+pub mod schedule;
+pub mod solver;
+pub mod targets;
+
+use fees::FeeModel;
+use locale::Locale;
+use money::{from_cents, to_cents};
+use plan::{Plan, PlanEntry};
+use solver::{Choice, Objective, ObjectiveWeights, Solver, Stop};
 
 pub type Error = Box<dyn std::error::Error>;
 
+/// No combination of orders fits into the reinvest amount
+#[derive(Debug)]
+pub struct InfeasibleError;
+
+impl std::fmt::Display for InfeasibleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "No optimal new amounts found")
+    }
+}
+
+impl std::error::Error for InfeasibleError {}
+
+/// What to do with goal ratios not summing up to 1
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum RatioPolicy {
+    /// Scale the goal ratios to sum up to 1, warning if they do not
+    #[default]
+    Normalize,
+    /// Keep the shortfall to 1 as cash, failing if the goal ratios exceed 1
+    Cash,
+    /// Fail unless the goal ratios sum up to 1
+    Strict,
+}
+
+/// How to rank the underweight stocks to fund first
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Underweight {
+    /// Amount missing to the goal value, ranking like the absolute drift
+    Gap,
+    /// Amount missing relative to the goal value
+    Relative,
+}
+
+/// Relative price difference tolerated when merging the same stock
+pub const MERGE_PRICE_TOLERANCE: f64 = 0.01;
+
 #[allow(non_snake_case)]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Stock {
+    #[serde(alias = "wkn")]
     pub WKN: String,
+    #[serde(alias = "isin")]
     pub ISIN: String,
+    #[serde(alias = "price")]
     pub Price: f64,
-    pub Shares: i32,
+    #[serde(alias = "shares")]
+    pub Shares: f64,
+    /// Share of the portfolio value to aim for, may be left out when derived
+    /// from a target allocation
+    #[serde(default, alias = "goal_ratio", alias = "goalRatio")]
     pub GoalRatio: f64,
+    #[serde(alias = "symbol")]
     pub Symbol: String,
+    /// ISO code of the currency of `Price`, the base currency if empty
+    #[serde(default, alias = "currency", skip_serializing_if = "String::is_empty")]
+    pub Currency: String,
+    /// Decimals to round fractional shares to, unrounded if missing
+    #[serde(
+        default,
+        alias = "share_decimals",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ShareDecimals: Option<u32>,
+    /// Whether fractions of a share can be bought and sold, e.g. for an ETF
+    /// in a savings plan but not a single stock, following `fractional` of
+    /// the options if missing
+    #[serde(default, alias = "fractional", skip_serializing_if = "Option::is_none")]
+    pub Fractional: Option<bool>,
+    /// Shares can only be traded in multiples of this, e.g. 1000 for bonds
+    /// quoted per unit of nominal value
+    #[serde(default, alias = "lot_size", skip_serializing_if = "Option::is_none")]
+    pub LotSize: Option<f64>,
+    /// Percentage points the current ratio may deviate from the goal ratio
+    /// before the stock is traded again
+    #[serde(default, alias = "band", skip_serializing_if = "Option::is_none")]
+    pub Band: Option<f64>,
+    /// Percent of the price paid above it when buying and received below it
+    /// when selling, e.g. half the bid/ask spread of an illiquid ETF
+    #[serde(default, alias = "slippage", skip_serializing_if = "Option::is_none")]
+    pub Slippage: Option<f64>,
+    /// Purchase lots for the realized gains of sales, sold first in first out
+    #[serde(default, alias = "lots", skip_serializing_if = "Vec::is_empty")]
+    pub Lots: Vec<Lot>,
+    /// Shares to hold at least after the plan
+    #[serde(default, alias = "min_shares", skip_serializing_if = "Option::is_none")]
+    pub MinShares: Option<f64>,
+    /// Shares to hold at most after the plan
+    #[serde(default, alias = "max_shares", skip_serializing_if = "Option::is_none")]
+    pub MaxShares: Option<f64>,
+    /// Never buy this stock
+    #[serde(default, alias = "no_buy")]
+    pub NoBuy: bool,
+    /// Never sell this stock
+    #[serde(default, alias = "no_sell")]
+    pub NoSell: bool,
+    /// WKN of a similar security to buy when harvesting a loss of this stock
+    #[serde(
+        default,
+        alias = "alternative",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub Alternative: Option<String>,
+    /// Accounts to place purchases in, most preferred first
+    #[serde(default, alias = "location", skip_serializing_if = "Vec::is_empty")]
+    pub Location: Vec<String>,
 }
 
+/// Shares bought at once
 #[allow(non_snake_case)]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Lot {
+    /// Purchase date as YYYY-MM-DD
+    #[serde(alias = "date")]
+    pub Date: String,
+    #[serde(alias = "shares")]
+    pub Shares: f64,
+    /// Purchase price per share in the currency of the stock
+    #[serde(alias = "price")]
+    pub Price: f64,
+}
+
+impl Stock {
+    /// Cost of trading at the effective instead of the current price for an
+    /// order of `order_value`, negative for sales
+    pub fn slippage(&self, order_value: f64) -> f64 {
+        self.Slippage.unwrap_or(0.0) / 100.0 * order_value.abs()
+    }
+
+    /// Gain realized by selling `shares` at the current price, oldest lots
+    /// first. Shares not covered by lots count as bought at the current price.
+    pub fn realized_gain(&self, shares: f64) -> f64 {
+        let mut left = shares;
+        let mut gain = 0.0;
+        for lot in self.Lots.iter().sorted_by(|a, b| a.Date.cmp(&b.Date)) {
+            if left <= 0.0 {
+                break;
+            }
+            let sold = lot.Shares.min(left);
+            gain += sold * (self.Price - lot.Price);
+            left -= sold;
+        }
+        gain
+    }
+
+    /// Shares that can be sold first in first out realizing a gain of at most
+    /// `max_gain`, and without selling a lot at a loss if `no_loss`. Shares not
+    /// covered by lots are sold last at no gain.
+    pub fn sellable_shares(&self, max_gain: f64, no_loss: bool) -> f64 {
+        let mut sold: f64 = 0.0;
+        let mut gain = 0.0;
+        for lot in self.Lots.iter().sorted_by(|a, b| a.Date.cmp(&b.Date)) {
+            let lot_gain = self.Price - lot.Price;
+            if no_loss && lot_gain < 0.0 {
+                return sold.min(self.Shares);
+            }
+            if gain + lot.Shares * lot_gain > max_gain {
+                return (sold + (max_gain - gain) / lot_gain).clamp(0.0, self.Shares);
+            }
+            sold += lot.Shares;
+            gain += lot.Shares * lot_gain;
+        }
+        self.Shares
+    }
+
+    /// Adds a lot for bought shares or removes sold shares from the oldest
+    /// lots.
+    fn trade_lots(&mut self, new_amount: f64, date: &str) {
+        if new_amount > 0.0 {
+            self.Lots.push(Lot {
+                Date: date.to_owned(),
+                Shares: new_amount,
+                Price: self.Price,
+            });
+            return;
+        }
+        self.Lots.sort_by(|a, b| a.Date.cmp(&b.Date));
+        let mut left = -new_amount;
+        for lot in self.Lots.iter_mut() {
+            let sold = lot.Shares.min(left);
+            lot.Shares -= sold;
+            left -= sold;
+        }
+        self.Lots.retain(|lot| lot.Shares > 0.0);
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Portfolio {
+    #[serde(alias = "stocks")]
     pub Stocks: Vec<Stock>,
+    /// Goal ratio of cash on the scale of the goal ratios of the stocks, kept
+    /// from the reinvest amount or raised by selling
+    #[serde(
+        default,
+        alias = "cash_goal_ratio",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub CashGoalRatio: Option<f64>,
+    /// Name of the account holding the stocks, e.g. a taxable or a retirement
+    /// account
+    #[serde(default, alias = "account", skip_serializing_if = "Option::is_none")]
+    pub Account: Option<String>,
+    /// ISO code of the currency the plan is reported in, stocks in other
+    /// currencies are converted to it
+    #[serde(
+        default,
+        alias = "base_currency",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub BaseCurrency: Option<String>,
+    /// Cash balances by ISO currency code, free for purchases
+    #[serde(default, alias = "cash", skip_serializing_if = "BTreeMap::is_empty")]
+    pub Cash: BTreeMap<String, f64>,
+}
+
+/// Delimiter and decimal separator of CSV files, e.g. `;` and `,` for German
+/// spreadsheets.
+#[derive(Clone, Copy, Debug)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub decimal_comma: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: b',',
+            decimal_comma: false,
+        }
+    }
+}
+
+/// CSV columns holding numbers, which are affected by the decimal separator
+const NUMERIC_COLUMNS: &[&str] = &[
+    "Price",
+    "price",
+    "Shares",
+    "shares",
+    "GoalRatio",
+    "goal_ratio",
+    "goalRatio",
+    "LotSize",
+    "lot_size",
+    "Band",
+    "band",
+    "MinShares",
+    "min_shares",
+    "MaxShares",
+    "max_shares",
+    "Slippage",
+    "slippage",
+];
+
+impl Portfolio {
+    pub fn from_csv_reader<R: std::io::Read>(reader: R) -> Result<Self, Error> {
+        Self::from_csv_reader_with_dialect(reader, &CsvDialect::default())
+    }
+
+    pub fn from_csv_reader_with_dialect<R: std::io::Read>(
+        reader: R,
+        dialect: &CsvDialect,
+    ) -> Result<Self, Error> {
+        let mut csv_reader = csv::ReaderBuilder::new()
+            .delimiter(dialect.delimiter)
+            .from_reader(reader);
+        let headers = csv_reader.headers()?.clone();
+
+        let mut stocks = Vec::new();
+        for record in csv_reader.records() {
+            let record = match dialect.decimal_comma {
+                true => convert_numeric_columns(&headers, &record?, |value| {
+                    value.replace('.', "").replace(',', ".")
+                }),
+                false => record?,
+            };
+            stocks.push(record.deserialize::<Stock>(Some(&headers))?);
+        }
+        Ok(Portfolio {
+            Stocks: stocks,
+            ..Default::default()
+        })
+    }
+
+    pub fn to_csv_writer<W: std::io::Write>(&self, writer: W) -> Result<(), Error> {
+        self.to_csv_writer_with_dialect(writer, &CsvDialect::default())
+    }
+
+    pub fn to_csv_writer_with_dialect<W: std::io::Write>(
+        &self,
+        writer: W,
+        dialect: &CsvDialect,
+    ) -> Result<(), Error> {
+        if self.Stocks.iter().any(|s| !s.Lots.is_empty()) {
+            simple_error::bail!("Lots cannot be written to CSV, use JSON instead");
+        }
+        if self.Stocks.iter().any(|s| !s.Location.is_empty()) {
+            simple_error::bail!("Locations cannot be written to CSV, use JSON instead");
+        }
+        if !self.Cash.is_empty() {
+            simple_error::bail!("Cash cannot be written to CSV, use JSON instead");
+        }
+        // Serialize every stock alone with the default dialect first to get
+        // its fields as strings. Unset optional fields are left out, so the
+        // columns are those of any stock and missing fields stay empty.
+        let mut columns: Vec<String> = Vec::new();
+        let mut rows: Vec<HashMap<String, String>> = Vec::new();
+        for stock in self.Stocks.iter() {
+            let mut buffer = csv::Writer::from_writer(Vec::new());
+            buffer.serialize(stock)?;
+            let buffer = buffer.into_inner().map_err(|e| e.into_error())?;
+            let mut csv_reader = csv::Reader::from_reader(buffer.as_slice());
+            let headers = csv_reader.headers()?.clone();
+            let record = csv_reader.records().next().transpose()?.unwrap_or_default();
+            // New columns go after the column of the previous field
+            let mut position = 0;
+            for header in headers.iter() {
+                match columns.iter().position(|column| column == header) {
+                    Some(idx) => position = idx + 1,
+                    None => {
+                        columns.insert(position, header.to_owned());
+                        position += 1;
+                    }
+                }
+            }
+            rows.push(
+                headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(header, value)| (header.to_owned(), value.to_owned()))
+                    .collect(),
+            );
+        }
+        let headers = csv::StringRecord::from(columns);
+
+        let mut csv_writer = csv::WriterBuilder::new()
+            .delimiter(dialect.delimiter)
+            .from_writer(writer);
+        csv_writer.write_record(&headers)?;
+        for row in rows {
+            let record: csv::StringRecord = headers
+                .iter()
+                .map(|column| row.get(column).map_or("", String::as_str))
+                .collect();
+            let record = match dialect.decimal_comma {
+                true => convert_numeric_columns(&headers, &record, |value| value.replace('.', ",")),
+                false => record,
+            };
+            csv_writer.write_record(&record)?;
+        }
+        csv_writer.flush()?;
+        Ok(())
+    }
+
+    /// Adds the new shares of a plan to the holdings, updating the lots of
+    /// stocks tracking them.
+    pub fn apply(&mut self, new_amounts_map: &HashMap<String, f64>) {
+        let today = date::today();
+        for stock in self.Stocks.iter_mut() {
+            let new_amount = *new_amounts_map.get(&stock.WKN).unwrap_or(&0.0);
+            stock.Shares += new_amount;
+            if !stock.Lots.is_empty() && new_amount != 0.0 {
+                stock.trade_lots(new_amount, &today);
+            }
+        }
+    }
+
+    /// Sum of the cash balances, all in the base currency once converted with
+    /// [`fx::convert_to_base`]
+    pub fn free_cash(&self) -> f64 {
+        self.Cash.values().sum()
+    }
+
+    /// Takes `amount` from the cash balance in `currency`, adding to it if
+    /// negative.
+    pub fn withdraw_cash(&mut self, currency: &str, amount: f64) {
+        let balance = self.Cash.entry(currency.to_owned()).or_default();
+        *balance = money::round_cents(*balance - amount);
+    }
+
+    /// Current ratio of `stock` minus its normalized goal ratio, `None` for an
+    /// empty portfolio
+    pub fn drift(&self, stock: &Stock) -> Option<f64> {
+        let total: f64 = self.Stocks.iter().map(|s| s.Price * s.Shares).sum();
+        let ratio_sum: f64 = self.Stocks.iter().map(|s| s.GoalRatio).sum();
+        if total == 0.0 || ratio_sum == 0.0 {
+            return None;
+        }
+        Some(stock.Price * stock.Shares / total - stock.GoalRatio / ratio_sum)
+    }
+
+    /// Makes the goal ratios sum up to 1 by `policy`. A `CashGoalRatio` is
+    /// scaled along with the goal ratios of the stocks.
+    pub fn normalize_goal_ratios(&mut self, policy: RatioPolicy) -> Result<(), Error> {
+        let ratio_sum: f64 = self.Stocks.iter().map(|s| s.GoalRatio).sum();
+        if (ratio_sum - 1.0).abs() < 1e-6 {
+            return Ok(());
+        }
+        match policy {
+            RatioPolicy::Normalize => {
+                log::warn!("Goal ratios sum up to {ratio_sum:.4} and are scaled to 1");
+                for stock in self.Stocks.iter_mut() {
+                    stock.GoalRatio /= ratio_sum;
+                }
+                if let Some(cash_ratio) = self.CashGoalRatio.as_mut() {
+                    *cash_ratio /= ratio_sum;
+                }
+            }
+            RatioPolicy::Cash if ratio_sum < 1.0 => {
+                let cash_ratio = self.CashGoalRatio.get_or_insert(0.0);
+                *cash_ratio += 1.0 - ratio_sum;
+            }
+            RatioPolicy::Cash | RatioPolicy::Strict => {
+                simple_error::bail!("Goal ratios sum up to {:.4} instead of 1", ratio_sum)
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the band of the stocks without one to `absolute` percentage points
+    /// or `relative` percent of the goal ratio, whichever is smaller, e.g. the
+    /// 5/25 rule.
+    pub fn apply_band_rule(&mut self, absolute: f64, relative: f64) {
+        let ratio_sum: f64 = self.Stocks.iter().map(|s| s.GoalRatio).sum();
+        for stock in self.Stocks.iter_mut().filter(|s| s.Band.is_none()) {
+            let goal_percent = stock.GoalRatio / ratio_sum * 100.0;
+            stock.Band = Some(absolute.min(relative / 100.0 * goal_percent));
+        }
+    }
+
+    /// Cash to keep by the `CashGoalRatio` once `reinvest` is added to the
+    /// portfolio
+    pub fn cash_goal(&self, reinvest: f64) -> f64 {
+        let cash_ratio = self.CashGoalRatio.unwrap_or_default();
+        if cash_ratio == 0.0 {
+            return 0.0;
+        }
+        let total: f64 = self.Stocks.iter().map(|s| s.Price * s.Shares).sum::<f64>() + reinvest;
+        let ratio_sum: f64 = self.Stocks.iter().map(|s| s.GoalRatio).sum::<f64>() + cash_ratio;
+        cash_ratio / ratio_sum * total
+    }
+
+    /// Merges portfolios, summing the shares of stocks with identical WKN and
+    /// the cash balances in the same currency.
+    ///
+    /// Goal ratios of merged stocks have to match, prices may differ by up to
+    /// `MERGE_PRICE_TOLERANCE` since brokers quote slightly different prices.
+    pub fn merge(portfolios: Vec<Portfolio>) -> Result<Self, Error> {
+        let mut cash_goal_ratio = None;
+        for ratio in portfolios.iter().filter_map(|p| p.CashGoalRatio) {
+            match cash_goal_ratio {
+                Some(existing) if existing != ratio => {
+                    simple_error::bail!("Inconsistent cash goal ratios {} and {}", existing, ratio)
+                }
+                _ => cash_goal_ratio = Some(ratio),
+            }
+        }
+
+        let mut base_currency: Option<String> = None;
+        for currency in portfolios.iter().filter_map(|p| p.BaseCurrency.as_ref()) {
+            match &base_currency {
+                Some(existing) if existing != currency => {
+                    simple_error::bail!(
+                        "Inconsistent base currencies {} and {}",
+                        existing,
+                        currency
+                    )
+                }
+                _ => base_currency = Some(currency.clone()),
+            }
+        }
+
+        // Merged accounts only keep a name they share
+        let account = portfolios
+            .first()
+            .and_then(|p| p.Account.clone())
+            .filter(|name| portfolios.iter().all(|p| p.Account.as_ref() == Some(name)));
+
+        let mut cash = BTreeMap::new();
+        for (currency, amount) in portfolios.iter().flat_map(|p| p.Cash.iter()) {
+            *cash.entry(currency.clone()).or_default() += amount;
+        }
+
+        let mut stocks: Vec<Stock> = Vec::new();
+        for stock in portfolios.into_iter().flat_map(|p| p.Stocks) {
+            match stocks.iter_mut().find(|s| s.WKN == stock.WKN) {
+                Some(existing) => {
+                    if (existing.GoalRatio - stock.GoalRatio).abs() > f64::EPSILON {
+                        simple_error::bail!(
+                            "Inconsistent goal ratios {} and {} for {}",
+                            existing.GoalRatio,
+                            stock.GoalRatio,
+                            stock.WKN
+                        );
+                    }
+                    if (existing.Price - stock.Price).abs() > MERGE_PRICE_TOLERANCE * existing.Price
+                    {
+                        simple_error::bail!(
+                            "Inconsistent prices {} and {} for {}",
+                            existing.Price,
+                            stock.Price,
+                            stock.WKN
+                        );
+                    }
+                    existing.Shares += stock.Shares;
+                    existing.Lots.extend(stock.Lots);
+                }
+                None => stocks.push(stock),
+            }
+        }
+        Ok(Portfolio {
+            Stocks: stocks,
+            CashGoalRatio: cash_goal_ratio,
+            Account: account,
+            BaseCurrency: base_currency,
+            Cash: cash,
+        })
+    }
+}
+
+fn convert_numeric_columns(
+    headers: &csv::StringRecord,
+    record: &csv::StringRecord,
+    convert: impl Fn(&str) -> String,
+) -> csv::StringRecord {
+    headers
+        .iter()
+        .zip(record.iter())
+        .map(|(header, value)| match NUMERIC_COLUMNS.contains(&header) {
+            true => convert(value),
+            false => value.to_owned(),
+        })
+        .collect()
 }
 
+/// Settings of the optimization
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReinvestOptions {
+    /// Prohibit selling of stocks
+    pub no_selling: bool,
+    pub solver: Solver,
+    /// Allow fractional shares, rounded to the `ShareDecimals` of a stock if
+    /// given, for stocks without a `Fractional` of their own
+    pub fractional: bool,
+    /// Fees of every order, paid from the reinvest amount
+    pub fees: FeeModel,
+    /// Investment an order has to be worth to be placed, trading a lower
+    /// invested amount for fewer orders. Orders of stocks traded in any
+    /// fraction are always placed.
+    pub trade_penalty: f64,
+    /// Number of stocks to spread the reinvest amount across at most
+    pub max_trades: Option<usize>,
+    pub objective: Objective,
+    /// Weights of the terms of the `Weighted` objective
+    pub weights: ObjectiveWeights,
+    /// Tax rate on the realized gains of sales, paid from the proceeds
+    pub tax_rate: f64,
+    /// Value a single order may have at most, buying or selling
+    pub max_order: Option<f64>,
+    /// Fee an order may cost at most as fraction of its value, dropping
+    /// smaller orders in favor of the others
+    pub max_fee_ratio: Option<f64>,
+    /// Trade stocks outside of their band only back to its nearest edge
+    pub to_band_edge: bool,
+    /// Fraction of the shares of a stock with a goal ratio of 0 to sell at
+    /// most, winding it down over several runs
+    pub wind_down: Option<f64>,
+    /// Never sell shares of a lot bought above the current price
+    pub no_loss_sales: bool,
+    /// Net gain all sales may realize at most, e.g. the remaining tax
+    /// allowance of the year
+    pub max_realized_gain: Option<f64>,
+    /// Time after which the solver settles for the best rounding found so far
+    pub time_limit: Option<Duration>,
+    /// Close the gaps of the most underweight stocks first when the purchases
+    /// cannot close all of them, instead of buying a bit of every stock
+    pub underweight_first: Option<Underweight>,
+}
+
+/// Invested amount, new amounts by WKN and why the solver stopped before
+/// proving its rounding optimal, if it did
+pub type Reinvestment = (f64, HashMap<String, f64>, Option<Stop>);
+
 pub fn calculate_optimal_reinvest(
     portfolio: &Portfolio,
     reinvest_amount: f64,
-    no_selling: bool,
-) -> Result<(f64, HashMap<String, i32>), Error> {
-    let (selected_stocks, fractional_new_amounts) =
-        get_fractional_reinvest_amounts(portfolio, reinvest_amount, no_selling);
-    let rounding_combis = get_rounding_combinations(selected_stocks.len());
+    options: &ReinvestOptions,
+) -> Result<Reinvestment, Error> {
+    optimize_reinvest(portfolio, reinvest_amount, options, None)
+}
 
-    let (optimal_new_amounts, optimal_reinvest) = rounding_combis
+/// Like [`calculate_optimal_reinvest`] after a small change of the portfolio
+/// or the reinvest amount, e.g. an updated price. The search starts from the
+/// `previous` new amounts by WKN if they are still a candidate rounding within
+/// the budget, which cuts off most branches right away.
+pub fn reoptimize_reinvest(
+    portfolio: &Portfolio,
+    reinvest_amount: f64,
+    options: &ReinvestOptions,
+    previous: &HashMap<String, f64>,
+) -> Result<Reinvestment, Error> {
+    optimize_reinvest(portfolio, reinvest_amount, options, Some(previous))
+}
+
+fn optimize_reinvest(
+    portfolio: &Portfolio,
+    reinvest_amount: f64,
+    options: &ReinvestOptions,
+    previous: Option<&HashMap<String, f64>>,
+) -> Result<Reinvestment, Error> {
+    let deadline = options.time_limit.map(|limit| Instant::now() + limit);
+    // The stocks share what is left after the cash goal, which takes sales
+    // unless selling is prohibited
+    let reinvest_amount = match options.no_selling {
+        true => (reinvest_amount - portfolio.cash_goal(reinvest_amount)).max(0.0),
+        false => reinvest_amount - portfolio.cash_goal(reinvest_amount),
+    };
+    let (mut selected_stocks, mut fractional_new_amounts) =
+        get_fractional_reinvest_amounts(portfolio, reinvest_amount, options);
+    // Cash needed for `new_amount` shares of `stock` including fees, slippage
+    // and the tax on the gains of a sale
+    let cost = |stock: &Stock, new_amount: f64| {
+        let order_value = new_amount * stock.Price;
+        let tax = match new_amount < 0.0 {
+            true => options.tax_rate * stock.realized_gain(-new_amount),
+            false => 0.0,
+        };
+        order_value + options.fees.fee(order_value) + stock.slippage(order_value) + tax
+    };
+    if !options.fees.is_free()
+        || options.tax_rate != 0.0
+        || portfolio.Stocks.iter().any(|s| s.Slippage.is_some())
+    {
+        // Aim at investing what is left after the fees and taxes of the
+        // fractional orders
+        let charges: f64 = selected_stocks
+            .iter()
+            .zip(fractional_new_amounts.iter())
+            .map(|(stock, new_amount)| cost(stock, *new_amount) - new_amount * stock.Price)
+            .sum();
+        (selected_stocks, fractional_new_amounts) =
+            get_fractional_reinvest_amounts(portfolio, reinvest_amount - charges, options);
+    }
+
+    // Only stocks traded in multiples of a unit need rounding, the others get
+    // their fractional amount
+    let units = selected_stocks
+        .iter()
+        .map(|stock| trading_unit(stock, options.fractional))
+        .collect_vec();
+    let unrounded_cost: i64 = selected_stocks
         .iter()
-        .filter_map(|combi| {
-            let rounded_new_amounts = combi
+        .zip(fractional_new_amounts.iter())
+        .zip(units.iter())
+        .filter(|(_, unit)| unit.is_none())
+        .map(|((stock, new_amount), _)| to_cents(cost(stock, *new_amount)))
+        .sum();
+
+    // Rounding down and up in units for each rounded stock, and no order at
+    // all if orders are penalized
+    let candidates = selected_stocks
+        .iter()
+        .zip(fractional_new_amounts.iter())
+        .zip(units.iter())
+        .filter_map(|((stock, new_amount), unit)| {
+            let unit = (*unit)?;
+            let amount = new_amount / unit.shares();
+            let mut counts = vec![amount.floor(), amount.ceil()];
+            if options.trade_penalty > 0.0 && !counts.contains(&0.0) {
+                counts.push(0.0);
+            }
+            // Whole units within the share bounds, the lower bound winning if
+            // no unit fits between them
+            let (lower, upper) = share_bounds(stock, options);
+            let min_count = (lower / unit.shares() - 1e-9).ceil();
+            let max_count = (upper / unit.shares() + 1e-9).floor().max(min_count);
+            for count in counts.iter_mut() {
+                if (unit.times(*count) * stock.Price).abs() < min_order_value(options) {
+                    *count = 0.0;
+                }
+                *count = count.clamp(min_count, max_count);
+            }
+            Some((stock, unit, counts))
+        })
+        .collect_vec();
+
+    // Deviations are measured against the goal values of the portfolio with
+    // the whole reinvest amount invested. Stocks to exit which cannot be sold
+    // are held aside.
+    let goal_total = portfolio
+        .Stocks
+        .iter()
+        .filter(|s| !held_exit(s, options))
+        .map(|s| s.Price * s.Shares)
+        .sum::<f64>()
+        + reinvest_amount;
+    let ratio_sum: f64 = portfolio.Stocks.iter().map(|s| s.GoalRatio).sum();
+    let deviations = candidates
+        .iter()
+        .map(|(stock, unit, counts)| {
+            let goal_value = stock.GoalRatio / ratio_sum * goal_total;
+            counts
                 .iter()
-                .zip(fractional_new_amounts.iter())
-                .map(|(round_up, new_amount)| match round_up {
-                    true => new_amount.ceil(),
-                    false => new_amount.floor(),
+                .map(|&count| {
+                    let order_value = unit.times(count) * stock.Price;
+                    Deviation {
+                        drift: (stock.Price * stock.Shares + order_value - goal_value).abs(),
+                        orders: usize::from(count != 0.0),
+                        count,
+                    }
                 })
-                .collect_vec();
+                .collect_vec()
+        })
+        .collect_vec();
+    let items = candidates
+        .iter()
+        .map(|(stock, unit, counts)| {
+            let goal_value = stock.GoalRatio / ratio_sum * goal_total;
+            counts
+                .iter()
+                .map(|&count| {
+                    let order_value = unit.times(count) * stock.Price;
+                    let deviation = stock.Price * stock.Shares + order_value - goal_value;
+                    let orders = usize::from(count != 0.0);
+                    let penalty = match count == 0.0 {
+                        true => 0.0,
+                        false => options.trade_penalty,
+                    };
+                    // Scores are amounts of money, so penalties and tolerances
+                    // apply to all objectives alike. Costs and scores are whole
+                    // cents to keep their sums exact.
+                    let score = match options.objective {
+                        Objective::Invested => order_value,
+                        Objective::L1 => -deviation.abs(),
+                        Objective::L2 => -deviation * deviation / goal_total,
+                        Objective::Weighted => {
+                            let weights = options.weights;
+                            weights.invested * order_value
+                                - weights.drift * deviation.abs()
+                                - weights.trades * (orders as f64)
+                                - weights.fees
+                                    * (options.fees.fee(order_value) + stock.slippage(order_value))
+                        }
+                    };
+                    Choice {
+                        cost: to_cents(cost(stock, unit.times(count))) as f64,
+                        score: to_cents(score - penalty) as f64,
+                    }
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+    let remainders = fractional_new_amounts
+        .iter()
+        .zip(units.iter())
+        .filter_map(|(new_amount, unit)| {
+            let amount = new_amount / unit.as_ref()?.shares();
+            Some(amount - amount.floor())
+        })
+        .collect_vec();
 
-            let reinvest_sum: f64 = rounded_new_amounts
+    let budget = (to_cents(reinvest_amount) - unrounded_cost) as f64;
+    // The previous rounding if every amount is still a candidate
+    let incumbent = previous
+        .and_then(|previous| {
+            candidates
+                .iter()
+                .map(|(stock, unit, counts)| {
+                    let new_amount = previous.get(&stock.WKN).copied().unwrap_or(0.0);
+                    counts
+                        .iter()
+                        .position(|&count| (unit.times(count) - new_amount).abs() < 1e-9)
+                })
+                .collect::<Option<Vec<_>>>()
+        })
+        .filter(|chosen| {
+            let cost: f64 = chosen
                 .iter()
-                .zip(selected_stocks.iter())
-                .map(|(new_amount, stock)| new_amount * stock.Price)
+                .zip(items.iter())
+                .map(|(&c, i)| i[c].cost)
                 .sum();
+            cost <= budget
+        });
+    if previous.is_some() {
+        log::debug!(
+            "Warm start from the previous rounding: {}",
+            incumbent.is_some()
+        );
+    }
+    let (chosen, stop) = match options.solver {
+        Solver::BranchAndBound => find_rounding(&items, budget, deadline, incumbent.as_deref()),
+        Solver::Exhaustive => enumerate_choices(&items, budget, deadline)?,
+        Solver::Greedy => (round_largest_remainders(&items, &remainders, budget), None),
+        Solver::Nearest => (round_nearest(&items, &remainders, budget), None),
+    };
+    let chosen = chosen.ok_or(InfeasibleError)?;
+    let wkns = candidates
+        .iter()
+        .map(|(stock, _, _)| &stock.WKN)
+        .collect_vec();
+    let chosen = break_ties(&items, &deviations, &wkns, budget, chosen);
 
-            match reinvest_sum > reinvest_amount {
-                true => None,
-                false => Some((rounded_new_amounts, reinvest_sum)),
-            }
+    let mut rounded = candidates
+        .iter()
+        .zip(chosen.iter())
+        .map(|((_, unit, counts), &choice)| unit.times(counts[choice]));
+    let optimal_new_amounts = fractional_new_amounts
+        .iter()
+        .zip(units.iter())
+        .map(|(new_amount, unit)| match unit {
+            Some(_) => rounded.next().expect("one rounding per unit amount"),
+            None => *new_amount,
         })
-        .max_by(|a, b| a.1.total_cmp(&b.1))
-        .ok_or::<Error>(simple_error::simple_error!("No optimal new amounts found").into())?;
+        .collect_vec();
+    let optimal_reinvest = from_cents(
+        optimal_new_amounts
+            .iter()
+            .zip(selected_stocks.iter())
+            .map(|(new_amount, stock)| to_cents(new_amount * stock.Price))
+            .sum(),
+    );
 
-    let new_amounts_map: HashMap<String, i32> = selected_stocks
+    let new_amounts_map: HashMap<String, f64> = selected_stocks
         .iter()
         .zip(optimal_new_amounts.iter())
-        .map(|(stock, new_amount)| (stock.WKN.clone(), *new_amount as i32))
+        .map(|(stock, new_amount)| (stock.WKN.clone(), *new_amount))
         .collect();
-    Ok((optimal_reinvest, new_amounts_map))
+    Ok((optimal_reinvest, new_amounts_map, stop))
 }
 
-pub fn print_reinvest(
-    portfolio: &Portfolio,
-    new_amounts_map: &HashMap<String, i32>,
-    optimal_reinvest: f64,
-) {
-    let actual_sum = portfolio.Stocks.iter().fold(0.0, |acc, elem| {
-        acc + elem.Price * (elem.Shares + new_amounts_map.get(&elem.WKN).unwrap_or(&0)) as f64
+/// Remaining drift and orders of a choice, deciding between choices of the
+/// same score
+#[derive(Clone, Copy, Debug)]
+struct Deviation {
+    /// Absolute deviation from the goal value
+    drift: f64,
+    orders: usize,
+    count: f64,
+}
+
+/// Moves `chosen` towards the preferred plan among those with the same score.
+///
+/// Of plans with the same score to the cent, the one with the lowest remaining
+/// drift wins, then the one with the fewest orders, then the one with more
+/// units of the stock first by WKN. Starting from `chosen`, the choices of
+/// single items and pairs of items are changed as long as that keeps the score
+/// and the budget and wins the tie. Tied plans differing in more than two items
+/// are never compared, so the solvers may still return different plans of the
+/// same score, each of them deterministically.
+fn break_ties(
+    items: &[Vec<Choice>],
+    deviations: &[Vec<Deviation>],
+    wkns: &[&String],
+    budget: f64,
+    mut chosen: Vec<usize>,
+) -> Vec<usize> {
+    let mut cost: f64 = items
+        .iter()
+        .zip(chosen.iter())
+        .map(|(c, &i)| c[i].cost)
+        .sum();
+    // Change of cost, score, drift and orders by switching item `idx` to
+    // `choice`
+    let delta = |chosen: &[usize], idx: usize, choice: usize| {
+        let (old, new) = (items[idx][chosen[idx]], items[idx][choice]);
+        let (old_dev, new_dev) = (deviations[idx][chosen[idx]], deviations[idx][choice]);
+        (
+            new.cost - old.cost,
+            new.score - old.score,
+            new_dev.drift - old_dev.drift,
+            new_dev.orders as isize - old_dev.orders as isize,
+        )
+    };
+    let wins = |score: f64, drift: f64, orders: isize, first_up: bool| {
+        score == 0.0
+            && (drift < -1e-9 || (drift.abs() <= 1e-9 && (orders < 0 || (orders == 0 && first_up))))
+    };
+    let count = |idx: usize, choice: usize| deviations[idx][choice].count;
+
+    let mut improved = true;
+    while improved {
+        improved = false;
+        for idx in 0..items.len() {
+            for choice in 0..items[idx].len() {
+                if choice == chosen[idx] {
+                    continue;
+                }
+                let (c, s, d, o) = delta(&chosen, idx, choice);
+                if cost + c <= budget && wins(s, d, o, false) {
+                    cost += c;
+                    chosen[idx] = choice;
+                    improved = true;
+                    continue;
+                }
+                for other in idx + 1..items.len() {
+                    for other_choice in 0..items[other].len() {
+                        if other_choice == chosen[other] || choice == chosen[idx] {
+                            continue;
+                        }
+                        let (oc, os, od, oo) = delta(&chosen, other, other_choice);
+                        let first = match wkns[idx] < wkns[other] {
+                            true => (idx, choice),
+                            false => (other, other_choice),
+                        };
+                        let first_up = count(first.0, first.1) > count(first.0, chosen[first.0]);
+                        if cost + c + oc <= budget && wins(s + os, d + od, o + oo, first_up) {
+                            cost += c + oc;
+                            chosen[idx] = choice;
+                            chosen[other] = other_choice;
+                            improved = true;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    chosen
+}
+
+/// Smallest tradable quantity of a stock, `lot` shares divided by
+/// `10^decimals`
+#[derive(Clone, Copy, Debug)]
+struct TradingUnit {
+    lot: f64,
+    decimals: i32,
+}
+
+impl TradingUnit {
+    fn shares(&self) -> f64 {
+        self.lot / 10f64.powi(self.decimals)
+    }
+
+    /// Number of shares of `count` units, dividing last to keep decimal
+    /// quantities like `0.3` exact
+    fn times(&self, count: f64) -> f64 {
+        count * self.lot / 10f64.powi(self.decimals)
+    }
+}
+
+/// Trading unit of a stock, `None` if any fraction can be traded. The
+/// `Fractional` of the stock overrides `fractional`, and lot sizes apply in
+/// fractional mode as well.
+fn trading_unit(stock: &Stock, fractional: bool) -> Option<TradingUnit> {
+    let fractional = stock.Fractional.unwrap_or(fractional);
+    match (stock.LotSize, fractional, stock.ShareDecimals) {
+        (Some(lot), _, _) => Some(TradingUnit { lot, decimals: 0 }),
+        (None, false, _) => Some(TradingUnit {
+            lot: 1.0,
+            decimals: 0,
+        }),
+        (None, true, Some(decimals)) => Some(TradingUnit {
+            lot: 1.0,
+            decimals: decimals as i32,
+        }),
+        (None, true, None) => None,
+    }
+}
+
+/// Finds the choices with the best score within the budget with
+/// branch-and-bound, and why the search stopped early if it did.
+/// Costs, scores and the budget are in whole cents.
+///
+/// If every score is the cost, i.e. the invested amount is maximized without
+/// fees, this is a subset sum over the extra cents of rounding up, which is
+/// optimal and never exceeds the budget.
+fn find_rounding(
+    items: &[Vec<Choice>],
+    budget: f64,
+    deadline: Option<Instant>,
+    incumbent: Option<&[usize]>,
+) -> (Option<Vec<usize>>, Option<Stop>) {
+    if !items
+        .iter()
+        .all(|c| c.len() == 2 && c.iter().all(|c| c.score == c.cost))
+    {
+        return solver::best_choices(items, budget, 0.0, deadline, incumbent);
+    }
+
+    let capacity = budget - items.iter().map(|c| c[0].cost).sum::<f64>();
+    if capacity < 0.0 {
+        return (None, None);
+    }
+    let weights = items
+        .iter()
+        .map(|c| (c[1].cost - c[0].cost).max(0.0) as u64)
+        .collect_vec();
+    let taken = incumbent.map(|chosen| chosen.iter().map(|&c| c == 1).collect_vec());
+    let (round_up, stop) =
+        solver::max_subset_sum(&weights, capacity as u64, deadline, taken.as_deref());
+    (Some(round_up.into_iter().map(usize::from).collect()), stop)
+}
+
+/// Rounds every amount to the nearest unit, then rounds down the amounts with
+/// the smallest remainder first until the budget suffices.
+fn round_nearest(items: &[Vec<Choice>], remainders: &[f64], budget: f64) -> Option<Vec<usize>> {
+    let mut chosen = remainders
+        .iter()
+        .map(|&remainder| usize::from(remainder >= 0.5))
+        .collect_vec();
+    let mut cost: f64 = items
+        .iter()
+        .zip(chosen.iter())
+        .map(|(choices, &choice)| choices[choice].cost)
+        .sum();
+    let by_remainder = (0..items.len())
+        .filter(|&idx| chosen[idx] == 1)
+        .sorted_by(|&a, &b| remainders[a].total_cmp(&remainders[b]));
+    for idx in by_remainder {
+        if cost <= budget {
+            break;
+        }
+        cost -= items[idx][1].cost - items[idx][0].cost;
+        chosen[idx] = 0;
+    }
+    (cost <= budget).then_some(chosen)
+}
+
+/// Rounds up the amounts with the largest remainder first, as long as the
+/// budget suffices and it improves the score. Purchases scoring worse than
+/// placing no order are dropped afterwards.
+fn round_largest_remainders(
+    items: &[Vec<Choice>],
+    remainders: &[f64],
+    budget: f64,
+) -> Option<Vec<usize>> {
+    let mut left = budget - items.iter().map(|c| c[0].cost).sum::<f64>();
+    if left < 0.0 {
+        return None;
+    }
+
+    let mut chosen = vec![0; items.len()];
+    let by_remainder = (0..items.len())
+        .filter(|&idx| remainders[idx] != 0.0)
+        .sorted_by(|&a, &b| {
+            let value = |idx: usize| remainders[idx] * (items[idx][1].score - items[idx][0].score);
+            value(b).total_cmp(&value(a))
+        });
+    for idx in by_remainder {
+        let extra = items[idx][1].cost - items[idx][0].cost;
+        if extra <= left && items[idx][1].score > items[idx][0].score {
+            left -= extra;
+            chosen[idx] = 1;
+        }
+    }
+    for (choice, choices) in chosen.iter_mut().zip(items.iter()) {
+        let (placed, none) = (choices[*choice], choices.get(2));
+        if none.is_some_and(|none| none.score > placed.score && none.cost < placed.cost) {
+            *choice = 2;
+        }
+    }
+    Some(chosen)
+}
+
+/// Evaluates all combinations of choices and picks the one with the best
+/// score within the budget.
+///
+/// Combinations are numbered like digits of a mixed-radix number with the
+/// last item changing fastest, and the numbers are split across one thread
+/// per core. Threads share the best score found so far, so combinations below
+/// it are discarded right away. Among equal scores the last combination wins,
+/// as in a sequential search. Counting up changes one digit most of the time,
+/// so the cost and score are updated by the digits changed only, which stays
+/// exact for whole cents.
+///
+/// Whenever the last digits are all 0, the combinations sharing the leading
+/// digits are skipped at once if even the best scores of the remaining items
+/// cannot reach the best score found so far, or their cheapest choices exceed
+/// the budget. This cuts the same combinations the search would discard, so
+/// the result does not change. After `deadline` the best combination found so
+/// far is returned along with the reason to stop. More combinations than fit
+/// in a `usize` are an error.
+fn enumerate_choices(
+    items: &[Vec<Choice>],
+    budget: f64,
+    deadline: Option<Instant>,
+) -> Result<(Option<Vec<usize>>, Option<Stop>), Error> {
+    let Some(combinations) = items
+        .iter()
+        .try_fold(1_usize, |acc, choices| acc.checked_mul(choices.len()))
+    else {
+        simple_error::bail!("Too many combinations to enumerate, use --solver branch-and-bound");
+    };
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = combinations.div_ceil(threads);
+    let best_score = AtomicU64::new(f64::NEG_INFINITY.to_bits());
+    let enumerated = AtomicUsize::new(0);
+    // Of the items from each index on, the sums of the first choices, the
+    // best scores and the lowest costs, and the number of their combinations
+    let mut rest = vec![(Choice::default(), Choice::default(), 1_usize); items.len() + 1];
+    for (idx, choices) in items.iter().enumerate().rev() {
+        let (first, bound, block) = rest[idx + 1];
+        let best = choices
+            .iter()
+            .map(|c| c.score)
+            .fold(f64::NEG_INFINITY, f64::max);
+        let cheapest = choices.iter().map(|c| c.cost).fold(f64::INFINITY, f64::min);
+        rest[idx] = (
+            Choice {
+                cost: first.cost + choices[0].cost,
+                score: first.score + choices[0].score,
+            },
+            Choice {
+                cost: bound.cost + cheapest,
+                score: bound.score + best,
+            },
+            block * choices.len(),
+        );
+    }
+
+    let combination = |mut number: usize| -> Vec<usize> {
+        let mut digits = vec![0; items.len()];
+        for (digit, choices) in digits.iter_mut().zip(items.iter()).rev() {
+            *digit = number % choices.len();
+            number /= choices.len();
+        }
+        digits
+    };
+
+    let best = thread::scope(|scope| {
+        let workers = (0..combinations)
+            .step_by(chunk_size)
+            .map(|start| {
+                let (best_score, enumerated, combination, rest) =
+                    (&best_score, &enumerated, &combination, &rest);
+                scope.spawn(move || {
+                    let mut best: Option<(f64, usize)> = None;
+                    let mut digits = combination(start);
+                    let (mut cost, mut score) = digits
+                        .iter()
+                        .zip(items.iter())
+                        .map(|(&digit, choices)| choices[digit])
+                        .fold((0.0, 0.0), |(cost, score), c| {
+                            (cost + c.cost, score + c.score)
+                        });
+                    let end = combinations.min(start + chunk_size);
+                    let mut number = start;
+                    let mut steps = 0;
+                    while number < end {
+                        steps += 1;
+                        if solver::past(deadline, steps) {
+                            break;
+                        }
+                        // Fewest leading digits followed by zeros only whose
+                        // combinations are all cut, down to the current one
+                        let best_known = f64::from_bits(best_score.load(Ordering::Relaxed));
+                        let mut fixed = digits.len();
+                        while fixed > 0 && digits[fixed - 1] == 0 {
+                            fixed -= 1;
+                        }
+                        let cut = (fixed..=digits.len()).find(|&level| {
+                            let (first, bound, _) = rest[level];
+                            score - first.score + bound.score < best_known
+                                || cost - first.cost + bound.cost > budget
+                        });
+                        let level = match cut {
+                            Some(0) => {
+                                number = end;
+                                break;
+                            }
+                            Some(level) => level,
+                            None => {
+                                best = Some((score, number));
+                                let _ = best_score.fetch_update(
+                                    Ordering::Relaxed,
+                                    Ordering::Relaxed,
+                                    |b| (score > f64::from_bits(b)).then_some(score.to_bits()),
+                                );
+                                digits.len()
+                            }
+                        };
+
+                        // Count up the leading digits, carrying towards the
+                        // first item
+                        number += rest[level].2;
+                        for (digit, choices) in digits[..level].iter_mut().zip(items.iter()).rev() {
+                            let old = choices[*digit];
+                            *digit = (*digit + 1) % choices.len();
+                            let new = choices[*digit];
+                            cost += new.cost - old.cost;
+                            score += new.score - old.score;
+                            if *digit != 0 {
+                                break;
+                            }
+                        }
+                    }
+                    let count = number.min(end) - start;
+                    enumerated.fetch_add(count, Ordering::Relaxed);
+                    best
+                })
+            })
+            .collect_vec();
+        workers
+            .into_iter()
+            .filter_map(|worker| worker.join().unwrap())
+            .max_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
     });
+    let enumerated = enumerated.into_inner();
+    let stopped = enumerated < combinations;
+    if stopped {
+        log::warn!(
+            "Stopped enumeration at the time limit after {enumerated} of {combinations} combinations"
+        );
+    }
+
+    Ok((
+        best.map(|(_, number)| combination(number)),
+        stopped.then_some(Stop::TimeLimit),
+    ))
+}
 
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Wkn,
+    Price,
+    Shares,
+    NewShares,
+    OrderValue,
+    GoalRatio,
+    CurrentRatio,
+    ActualRatio,
+    DriftImprovement,
+}
+
+impl Column {
+    pub const ALL: &'static [Column] = &[
+        Column::Wkn,
+        Column::Price,
+        Column::Shares,
+        Column::NewShares,
+        Column::OrderValue,
+        Column::GoalRatio,
+        Column::CurrentRatio,
+        Column::ActualRatio,
+        Column::DriftImprovement,
+    ];
+
+    fn title(&self) -> &'static str {
+        match self {
+            Column::Wkn => "WKN",
+            Column::Price => "Price",
+            Column::Shares => "Shares",
+            Column::NewShares => "New Shares",
+            Column::OrderValue => "Order Value",
+            Column::GoalRatio => "Goal Ratio",
+            Column::CurrentRatio => "Current Ratio",
+            Column::ActualRatio => "Actual Ratio",
+            Column::DriftImprovement => "Improvement",
+        }
+    }
+
+    fn value(&self, entry: &PlanEntry, locale: &Locale) -> String {
+        match self {
+            Column::Wkn => entry.WKN.clone(),
+            Column::Price => locale.money(entry.Price),
+            Column::Shares => locale.shares(entry.Shares),
+            Column::NewShares => locale.shares(entry.NewShares),
+            Column::OrderValue => locale.money(entry.OrderValue),
+            Column::GoalRatio => locale.number(entry.GoalRatio, 4),
+            Column::CurrentRatio => locale.number(entry.CurrentRatio, 4),
+            Column::ActualRatio => locale.number(entry.ActualRatio, 4),
+            Column::DriftImprovement => locale.number(entry.DriftImprovement, 4),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TableOptions {
+    pub columns: Vec<Column>,
+    /// Color rows by how close the actual ratio gets to the goal ratio
+    pub color: bool,
+    /// Absolute ratio deviation up to which a stock counts as on target
+    pub drift_tolerance: f64,
+    pub locale: Locale,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions {
+            columns: Column::ALL.to_vec(),
+            color: true,
+            drift_tolerance: 0.01,
+            locale: Locale::default(),
+        }
+    }
+}
+
+pub fn print_reinvest(plan: &Plan, options: &TableOptions) {
     let mut table = Table::new();
-    table.set_titles(row![
-        "WKN",
-        "Price",
-        "Shares",
-        "New Shares",
-        "Goal Ratio",
-        "Actual Ratio"
-    ]);
-
-    for stock in portfolio.Stocks.iter() {
-        let new_amount = new_amounts_map.get(&stock.WKN).unwrap_or(&0);
-        let actual_ratio = (stock.Price * (stock.Shares + new_amount) as f64) / actual_sum;
-        table.add_row(row![
-            stock.WKN,
-            stock.Price,
-            stock.Shares,
-            new_amount,
-            format!("{:.4}", stock.GoalRatio),
-            format!("{actual_ratio:.4}"),
-        ]);
+    table.set_titles(
+        options
+            .columns
+            .iter()
+            .map(|c| Cell::new(c.title()))
+            .collect(),
+    );
+
+    for entry in plan.Stocks.iter() {
+        let mut table_row: Row = options
+            .columns
+            .iter()
+            .map(|c| Cell::new(&c.value(entry, &options.locale)))
+            .collect();
+        if options.color {
+            let drift = (entry.ActualRatio - entry.GoalRatio).abs();
+            let color = match drift {
+                d if d <= options.drift_tolerance => color::GREEN,
+                d if d <= 2.0 * options.drift_tolerance => color::YELLOW,
+                _ => color::RED,
+            };
+            for cell in table_row.iter_mut() {
+                cell.style(Attr::ForegroundColor(color));
+            }
+        }
+        table.add_row(table_row);
     }
+    if plan.Cash != 0.0 {
+        // Cash is compared to the value of the stocks and the cash together
+        let new_cash = plan.new_cash();
+        table.add_row(
+            options
+                .columns
+                .iter()
+                .map(|c| match c {
+                    Column::Wkn => Cell::new("Cash"),
+                    Column::OrderValue => Cell::new(&options.locale.money(new_cash - plan.Cash)),
+                    Column::CurrentRatio => Cell::new(
+                        &options
+                            .locale
+                            .number(plan.Cash / (plan.CurrentValue + plan.Cash), 4),
+                    ),
+                    Column::ActualRatio => Cell::new(
+                        &options
+                            .locale
+                            .number(new_cash / (plan.NewValue + new_cash), 4),
+                    ),
+                    _ => Cell::new(""),
+                })
+                .collect(),
+        );
+    }
+    table.add_row(
+        options
+            .columns
+            .iter()
+            .map(|c| match c {
+                Column::Wkn => Cell::new("Total"),
+                Column::OrderValue => Cell::new(&options.locale.money(plan.Invested)),
+                _ => Cell::new(""),
+            })
+            .collect(),
+    );
     table.set_format(*format::consts::FORMAT_NO_BORDER);
 
-    println!("\n{table}\nWould reinvest {optimal_reinvest:.2}\n");
+    // Only printing to stdout applies colors, and only if it is a terminal
+    println!();
+    table.printstd();
+    let locale = &options.locale;
+    let summary = plan.summary();
+    println!(
+        "\nWould reinvest {}\nPortfolio value {} -> {}",
+        locale.money(summary.Invested),
+        locale.money(plan.CurrentValue),
+        locale.money(plan.NewValue)
+    );
+    if summary.Fees != 0.0 {
+        println!("Fees {}", locale.money(summary.Fees));
+    }
+    if summary.Slippage != 0.0 {
+        println!("Slippage {}", locale.money(summary.Slippage));
+    }
+    if summary.Taxes != 0.0 {
+        println!("Taxes {}", locale.money(summary.Taxes));
+        for entry in plan.Stocks.iter().filter(|e| e.Tax != 0.0) {
+            println!("  {} {}", entry.WKN, locale.money(entry.Tax));
+        }
+    }
+    if plan.CashGoal != 0.0 {
+        println!("Cash goal {}", locale.money(plan.CashGoal));
+    }
+    println!(
+        "Leftover cash {}\nTurnover {}\nOrders {} buy, {} sell\nRemaining drift {}\n",
+        locale.money(summary.LeftoverCash),
+        locale.money(summary.Turnover),
+        summary.BuyOrders,
+        summary.SellOrders,
+        locale.number(summary.RemainingDrift, 4)
+    );
+    if !plan.Transfers.is_empty() {
+        println!("Sales paying for purchases");
+        for transfer in plan.Transfers.iter() {
+            println!(
+                "  {} -> {} {}",
+                transfer.From,
+                transfer.To,
+                locale.money(transfer.Amount)
+            );
+        }
+        println!();
+    }
+    if plan.Stocks.iter().any(|e| e.NativeOrderValue.is_some()) {
+        println!("Orders in native currency");
+        for entry in plan.Stocks.iter() {
+            if let Some(value) = entry.NativeOrderValue.filter(|_| entry.NewShares != 0.0) {
+                println!(
+                    "  {} {} {} {}",
+                    entry.WKN,
+                    locale.shares(entry.NewShares),
+                    locale.money(value),
+                    entry.Currency
+                );
+            }
+        }
+        println!();
+    }
+    if !plan.Placements.is_empty() {
+        println!("Orders by account");
+        for placement in plan.Placements.iter() {
+            println!(
+                "  {} {} {} {}",
+                placement.Account,
+                locale.shares(placement.Shares),
+                placement.WKN,
+                locale.money(placement.Amount)
+            );
+        }
+        println!();
+    }
+    if !plan.Harvests.is_empty() {
+        println!("Harvestable losses");
+        for harvest in plan.Harvests.iter() {
+            println!(
+                "  Sell {} {} for a loss of {}, saving {} tax{}",
+                locale.shares(harvest.Shares),
+                harvest.WKN,
+                locale.money(harvest.Loss),
+                locale.money(harvest.TaxSaving),
+                harvest
+                    .Alternative
+                    .as_ref()
+                    .map_or(String::new(), |alternative| format!(", buy {alternative}"))
+            );
+        }
+        println!();
+    }
+    if let Some(dividends) = &plan.Dividends {
+        println!(
+            "Expected dividends {} per year",
+            locale.money(dividends.Annual)
+        );
+        for dividend in dividends.Upcoming.iter() {
+            println!(
+                "  {} {} ex {}{}",
+                dividend.WKN,
+                locale.money(dividend.Amount),
+                dividend.ExDate,
+                dividend
+                    .PaymentDate
+                    .as_ref()
+                    .map_or(String::new(), |date| format!(", paid {date}"))
+            );
+        }
+        println!();
+    }
+}
+
+/// Prints bars of the goal ratio and the ratios before and after the plan.
+pub fn print_allocation_chart(plan: &Plan) {
+    const WIDTH: f64 = 50.0;
+    let max_ratio = plan
+        .Stocks
+        .iter()
+        .flat_map(|e| [e.GoalRatio, e.CurrentRatio, e.ActualRatio])
+        .fold(0.0, f64::max);
+    let wkn_width = plan.Stocks.iter().map(|e| e.WKN.len()).max().unwrap_or(0);
+
+    println!();
+    for entry in plan.Stocks.iter() {
+        for (idx, (label, ratio)) in [
+            ("goal", entry.GoalRatio),
+            ("before", entry.CurrentRatio),
+            ("after", entry.ActualRatio),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let wkn = match idx {
+                0 => entry.WKN.as_str(),
+                _ => "",
+            };
+            let bar = "#".repeat((ratio / max_ratio * WIDTH).round() as usize);
+            println!(
+                "{wkn:wkn_width$} {label:6} {bar:width$} {:6.2}%",
+                ratio * 100.0,
+                width = WIDTH as usize
+            );
+        }
+    }
+    println!();
 }
 
-fn get_fractional_reinvest_amounts(
-    portfolio: &Portfolio,
+/// Writes the plan as GitHub-flavored Markdown table.
+pub fn write_reinvest_markdown<W: std::io::Write>(
+    mut writer: W,
+    plan: &Plan,
+    locale: &Locale,
+) -> Result<(), Error> {
+    writeln!(
+        writer,
+        "| WKN | Price | Shares | New Shares | Goal Ratio | Actual Ratio |"
+    )?;
+    writeln!(writer, "| --- | ---: | ---: | ---: | ---: | ---: |")?;
+    for entry in plan.Stocks.iter() {
+        writeln!(
+            writer,
+            "| {} | {} | {} | {} | {} | {} |",
+            entry.WKN,
+            locale.money(entry.Price),
+            locale.shares(entry.Shares),
+            locale.shares(entry.NewShares),
+            locale.number(entry.GoalRatio, 4),
+            locale.number(entry.ActualRatio, 4)
+        )?;
+    }
+    writeln!(writer, "\nWould reinvest {}", locale.money(plan.Invested))?;
+    Ok(())
+}
+
+fn get_fractional_reinvest_amounts<'a>(
+    portfolio: &'a Portfolio,
     reinvest: f64,
-    no_selling: bool,
-) -> (Vec<&Stock>, Vec<f64>) {
-    let mut selected_stocks = portfolio.Stocks.iter().collect_vec();
+    options: &ReinvestOptions,
+) -> (Vec<&'a Stock>, Vec<f64>) {
+    let mut selected_stocks = portfolio
+        .Stocks
+        .iter()
+        .filter(|stock| match within_band(portfolio, stock) {
+            true => {
+                log::debug!(
+                    "Stock {} is within its band and will be excluded",
+                    stock.WKN
+                );
+                false
+            }
+            false => true,
+        })
+        .collect_vec();
 
+    // Stocks held at a bound of their shares or at the edge of their band,
+    // with their new amount
+    let mut bounded: Vec<(&Stock, f64)> = match options.to_band_edge {
+        true => band_edges(portfolio, &selected_stocks, reinvest, options),
+        false => Vec::new(),
+    };
+    selected_stocks.retain(|stock| !bounded.iter().any(|(s, _)| s.WKN == stock.WKN));
     let new_amounts = loop {
         let selected_sum = selected_stocks
             .iter()
-            .fold(0.0, |acc, &elem| acc + elem.Price * (elem.Shares as f64));
-        let goal_sum = selected_sum + reinvest;
+            .fold(0.0, |acc, &elem| acc + elem.Price * elem.Shares);
+        let bounded_cost: f64 = bounded.iter().map(|(s, amount)| amount * s.Price).sum();
+        let goal_sum = selected_sum + reinvest - bounded_cost;
 
         let ratio_sum = selected_stocks
             .iter()
@@ -127,52 +1609,449 @@ fn get_fractional_reinvest_amounts(
         let new_amounts = selected_stocks
             .iter()
             .zip(goal_amounts.iter())
-            .map(|(&stock, goal_amount)| goal_amount - stock.Shares as f64)
+            .map(|(&stock, goal_amount)| goal_amount - stock.Shares)
             .collect_vec();
 
-        if no_selling {
-            // Find set of stocks for which we buy a positive amount
-            let new_selected_stocks = selected_stocks
-                .iter()
-                .zip(new_amounts.iter())
-                .filter_map(|(&stock, &new_amount)| match new_amount > 0.0 {
+        // Find set of stocks with amounts they may be traded in
+        let new_selected_stocks = selected_stocks
+            .iter()
+            .zip(new_amounts.iter())
+            .filter_map(|(&stock, &new_amount)| {
+                let allowed = match new_amount > 0.0 {
+                    true => !stock.NoBuy,
+                    false => !(options.no_selling || (stock.NoSell && new_amount < 0.0)),
+                };
+                match allowed {
                     true => Some(stock),
                     false => {
                         log::debug!(
-                            "Stock {} would have negative amount {:.3} and will be excluded",
+                            "Stock {} would have prohibited amount {:.3} and will be excluded",
                             stock.WKN,
                             new_amount
                         );
                         None
                     }
-                })
+                }
+            })
+            .collect_vec();
+
+        // If the set is not the same, re-enter the loop of calculating amounts
+        if new_selected_stocks.len() != selected_stocks.len() {
+            selected_stocks = new_selected_stocks;
+            continue;
+        }
+
+        // Hold stocks outside of their share bounds at the bound and distribute
+        // the rest among the others. Holding stocks above their upper bound
+        // leaves less for the others and holding those below their lower bound
+        // more, so only the side exceeding its bounds by the larger value is
+        // held at a time, the other side may be within its bounds afterwards.
+        let excess = |stock: &Stock, new_amount: f64| {
+            let (lower, upper) = share_bounds(stock, options);
+            let above = (new_amount - upper).max(0.0) * stock.Price;
+            let below = (lower - new_amount).max(0.0) * stock.Price;
+            (above, below)
+        };
+        let (above, below) = selected_stocks
+            .iter()
+            .zip(new_amounts.iter())
+            .map(|(stock, &new_amount)| excess(stock, new_amount))
+            .fold((0.0, 0.0), |(a, b), (above, below)| (a + above, b + below));
+        let (within, outside): (Vec<_>, Vec<_>) = selected_stocks
+            .iter()
+            .zip(new_amounts.iter())
+            .partition(|(stock, &new_amount)| match excess(stock, new_amount) {
+                (0.0, 0.0) => true,
+                (0.0, _) => above >= below,
+                _ => above < below,
+            });
+        if !outside.is_empty() {
+            for (&stock, &new_amount) in outside {
+                let (lower, upper) = share_bounds(stock, options);
+                log::debug!(
+                    "Stock {} would have amount {:.3} outside of its share bounds",
+                    stock.WKN,
+                    new_amount
+                );
+                bounded.push((stock, new_amount.clamp(lower, upper)));
+            }
+            selected_stocks = within.into_iter().map(|(&stock, _)| stock).collect();
+            continue;
+        }
+
+        // Bound the sale realizing the largest gain while all sales realize
+        // more than allowed. Gains are the larger of the sales rounded up and
+        // down, so no rounding exceeds the allowed gain.
+        if let Some(max_gain) = options.max_realized_gain {
+            let gain = |stock: &Stock, new_amount: f64| {
+                let (down, up) = rounded_sales(stock, new_amount, options);
+                stock.realized_gain(down).max(stock.realized_gain(up))
+            };
+            let gains = selected_stocks
+                .iter()
+                .zip(new_amounts.iter())
+                .map(|(stock, &new_amount)| (false, gain(stock, new_amount)))
+                .chain(
+                    bounded
+                        .iter()
+                        .map(|(stock, new_amount)| (true, gain(stock, *new_amount))),
+                )
                 .collect_vec();
+            let total: f64 = gains.iter().map(|(_, gain)| gain).sum();
+            let largest = gains
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, gain))| *gain > 0.0)
+                .max_by(|a, b| a.1 .1.total_cmp(&b.1 .1));
+            if let (true, Some((idx, &(is_bounded, gain)))) = (total > max_gain + 0.005, largest) {
+                let (stock, new_amount) = match is_bounded {
+                    true => bounded[idx - selected_stocks.len()],
+                    false => (selected_stocks[idx], new_amounts[idx]),
+                };
+                let allowance = (max_gain - (total - gain)).max(0.0);
+                let shares = whole_units(
+                    stock,
+                    stock.sellable_shares(allowance, options.no_loss_sales),
+                    options,
+                )
+                .min(-new_amount);
+                log::debug!(
+                    "Stock {} would realize a gain of {:.2} beyond the allowed and is sold less",
+                    stock.WKN,
+                    gain
+                );
+                match is_bounded {
+                    true => bounded[idx - selected_stocks.len()].1 = -shares,
+                    false => {
+                        bounded.push((stock, -shares));
+                        selected_stocks.remove(idx);
+                    }
+                }
+                continue;
+            }
+        }
 
-            // If the set is not the same, re-enter the loop of calculating amounts
-            if new_selected_stocks.len() != selected_stocks.len() {
-                selected_stocks = new_selected_stocks;
+        if let Some(priority) = options.underweight_first {
+            let budget = reinvest - bounded.iter().map(|(s, a)| a * s.Price).sum::<f64>();
+            let funded = match new_amounts.iter().all(|&new_amount| new_amount >= 0.0) {
+                true => fund_underweight(
+                    portfolio,
+                    &selected_stocks,
+                    reinvest,
+                    budget,
+                    priority,
+                    options,
+                ),
+                false => None,
+            };
+            if let Some(funded) = funded {
+                bounded.extend(funded);
+                selected_stocks.clear();
                 continue;
             }
         }
 
+        if let Some(max_trades) = options.max_trades {
+            if selected_stocks.len() > max_trades {
+                // Keep the stocks furthest below their goal
+                selected_stocks = selected_stocks
+                    .iter()
+                    .zip(new_amounts.iter())
+                    .sorted_by(|(a, a_amount), (b, b_amount)| {
+                        (*b_amount * b.Price).total_cmp(&(*a_amount * a.Price))
+                    })
+                    .take(max_trades)
+                    .map(|(&stock, _)| stock)
+                    .collect_vec();
+                continue;
+            }
+        }
+
+        // Drop the smallest order too small for its fee, one at a time
+        if let Some((idx, _)) = new_amounts
+            .iter()
+            .zip(selected_stocks.iter())
+            .map(|(new_amount, stock)| (new_amount * stock.Price).abs())
+            .enumerate()
+            .filter(|(_, value)| *value > 0.0 && *value < min_order_value(options))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+        {
+            log::debug!(
+                "Stock {} would have an order too small for its fee and will be excluded",
+                selected_stocks[idx].WKN
+            );
+            selected_stocks.remove(idx);
+            continue;
+        }
+
         break new_amounts;
     };
 
-    (selected_stocks, new_amounts)
+    let (bounded_stocks, bounded_amounts): (Vec<_>, Vec<_>) = bounded.into_iter().unzip();
+    (
+        selected_stocks.into_iter().chain(bounded_stocks).collect(),
+        new_amounts.into_iter().chain(bounded_amounts).collect(),
+    )
 }
 
-fn get_rounding_combinations(length: usize) -> Vec<Vec<bool>> {
-    let limit_number = (2_usize).pow(length as u32);
+/// New amounts closing the gaps of the `stocks` below their goal value after
+/// investing `reinvest`, most underweight first by `priority`, until `budget`
+/// is spent. The amount of a gap not closed completely is rounded down to
+/// whole units so the rest funds the next stock. `None` if the budget closes
+/// every gap anyway.
+fn fund_underweight<'a>(
+    portfolio: &Portfolio,
+    stocks: &[&'a Stock],
+    reinvest: f64,
+    budget: f64,
+    priority: Underweight,
+    options: &ReinvestOptions,
+) -> Option<Vec<(&'a Stock, f64)>> {
+    let goal_total = portfolio
+        .Stocks
+        .iter()
+        .filter(|s| !held_exit(s, options))
+        .map(|s| s.Price * s.Shares)
+        .sum::<f64>()
+        + reinvest;
+    let ratio_sum: f64 = portfolio.Stocks.iter().map(|s| s.GoalRatio).sum();
+    let gaps = stocks
+        .iter()
+        .filter_map(|&stock| {
+            let goal_value = stock.GoalRatio / ratio_sum * goal_total;
+            let gap = goal_value - stock.Price * stock.Shares;
+            (gap > 0.0).then_some((stock, gap, goal_value))
+        })
+        .collect_vec();
+    if gaps.iter().map(|(_, gap, _)| gap).sum::<f64>() <= budget {
+        return None;
+    }
 
-    (0..limit_number)
-        // Map to binary representation with length "length"
-        .map(|num| format!("{num:0length$b}"))
-        // Map to vector of booleans
-        .map(|combi_string| {
-            combi_string
-                .chars()
-                .map(|c| !matches!(c, '0'))
-                .collect_vec()
+    let mut left = budget;
+    let mut funded = Vec::new();
+    for (stock, gap, _) in gaps
+        .into_iter()
+        .sorted_by(|a, b| match priority {
+            Underweight::Gap => b.1.total_cmp(&a.1),
+            Underweight::Relative => (b.1 / b.2).total_cmp(&(a.1 / a.2)),
         })
-        .collect()
+        .take(options.max_trades.unwrap_or(usize::MAX))
+    {
+        if left < 0.01 {
+            break;
+        }
+        let (_, upper) = share_bounds(stock, options);
+        let new_amount = match gap > left {
+            true => whole_units(stock, left / stock.Price, options),
+            false => gap / stock.Price,
+        }
+        .min(upper);
+        if new_amount * stock.Price < min_order_value(options).max(0.01) {
+            continue;
+        }
+        log::debug!(
+            "Stock {} is underweight by {:.2} and funded with {:.2}",
+            stock.WKN,
+            gap,
+            new_amount * stock.Price
+        );
+        left -= new_amount * stock.Price;
+        funded.push((stock, new_amount));
+    }
+    Some(funded)
+}
+
+/// Shares sold by `new_amount` rounded down and up to whole trading units, 0
+/// for purchases
+fn rounded_sales(stock: &Stock, new_amount: f64, options: &ReinvestOptions) -> (f64, f64) {
+    let shares = (-new_amount).max(0.0);
+    match trading_unit(stock, options.fractional) {
+        Some(unit) => (
+            unit.times((shares / unit.shares() + 1e-9).floor()),
+            unit.times((shares / unit.shares() - 1e-9).ceil()),
+        ),
+        None => (shares, shares),
+    }
+}
+
+/// `shares` rounded down to whole trading units
+fn whole_units(stock: &Stock, shares: f64, options: &ReinvestOptions) -> f64 {
+    match trading_unit(stock, options.fractional) {
+        Some(unit) => unit.times((shares / unit.shares() + 1e-9).floor()),
+        None => shares,
+    }
+}
+
+/// Whether `stock` is to be exited but cannot be sold, so it is held as is
+fn held_exit(stock: &Stock, options: &ReinvestOptions) -> bool {
+    stock.GoalRatio == 0.0 && (options.no_selling || stock.NoSell)
+}
+
+/// Smallest order value allowed by the maximum fee ratio
+fn min_order_value(options: &ReinvestOptions) -> f64 {
+    options
+        .max_fee_ratio
+        .map_or(0.0, |ratio| options.fees.min_efficient_order(ratio))
+}
+
+/// Range of the new amount of `stock` keeping its shares within `MinShares`
+/// and `MaxShares`, its order within the maximum order value in whole trading
+/// units, the sale of a stock to exit within the wind-down fraction and sales
+/// out of lots without a loss if required. Shares beyond a bound are kept if
+/// trading towards it is prohibited.
+fn share_bounds(stock: &Stock, options: &ReinvestOptions) -> (f64, f64) {
+    let mut lower = stock
+        .MinShares
+        .map_or(f64::NEG_INFINITY, |min| min - stock.Shares);
+    let mut upper = stock
+        .MaxShares
+        .map_or(f64::INFINITY, |max| max - stock.Shares);
+    if let Some(max_order) = options.max_order {
+        // Whole trading units, so that rounding the new amount does not lose
+        // proceeds of a sale the fractional distribution relies on
+        let (sell, buy) = match trading_unit(stock, options.fractional) {
+            Some(unit) => {
+                let units = max_order / stock.Price / unit.shares();
+                (
+                    unit.times((-units - 1e-9).ceil()),
+                    unit.times((units + 1e-9).floor()),
+                )
+            }
+            None => (-max_order / stock.Price, max_order / stock.Price),
+        };
+        lower = lower.max(sell);
+        upper = upper.min(buy);
+    }
+    if let Some(fraction) = options.wind_down.filter(|_| stock.GoalRatio == 0.0) {
+        lower = lower.max(-fraction * stock.Shares);
+    }
+    if options.no_loss_sales {
+        lower = lower.max(-stock.sellable_shares(f64::INFINITY, true));
+    }
+    if stock.NoBuy {
+        lower = lower.min(0.0);
+    }
+    if options.no_selling || stock.NoSell {
+        upper = upper.max(0.0);
+    }
+    (lower, upper.max(lower))
+}
+
+/// New amounts moving the `stocks` with a band to its nearest edge after
+/// investing `reinvest`. Purchases are scaled down to what the reinvest amount
+/// and the sales pay for, and sales to what the purchases need beyond the
+/// reinvest amount, leaving no proceeds idle.
+fn band_edges<'a>(
+    portfolio: &Portfolio,
+    stocks: &[&'a Stock],
+    reinvest: f64,
+    options: &ReinvestOptions,
+) -> Vec<(&'a Stock, f64)> {
+    let total: f64 = portfolio.Stocks.iter().map(|s| s.Price * s.Shares).sum();
+    let ratio_sum: f64 = portfolio.Stocks.iter().map(|s| s.GoalRatio).sum();
+    if total == 0.0 || ratio_sum == 0.0 {
+        return Vec::new();
+    }
+
+    let mut edges = stocks
+        .iter()
+        .filter_map(|&stock| {
+            let band = stock.Band? / 100.0;
+            let goal_ratio = stock.GoalRatio / ratio_sum;
+            let edge = (stock.Price * stock.Shares / total)
+                .clamp((goal_ratio - band).max(0.0), goal_ratio + band);
+            let new_amount = edge * (total + reinvest) / stock.Price - stock.Shares;
+            let allowed = match new_amount > 0.0 {
+                true => !stock.NoBuy,
+                false => !(options.no_selling || stock.NoSell),
+            };
+            let (lower, upper) = share_bounds(stock, options);
+            allowed.then(|| (stock, new_amount.clamp(lower, upper)))
+        })
+        .collect_vec();
+
+    let sales: f64 = edges
+        .iter()
+        .map(|(stock, amount)| (-amount * stock.Price).max(0.0))
+        .sum();
+    let purchases: f64 = edges
+        .iter()
+        .map(|(stock, amount)| (amount * stock.Price).max(0.0))
+        .sum();
+    let (scale, buying) = match purchases > reinvest + sales {
+        true => (((reinvest + sales) / purchases).max(0.0), true),
+        false if sales > 0.0 => (((purchases - reinvest) / sales).clamp(0.0, 1.0), false),
+        false => (1.0, false),
+    };
+    for (_, amount) in edges
+        .iter_mut()
+        .filter(|(_, amount)| (*amount > 0.0) == buying)
+    {
+        *amount *= scale;
+    }
+    edges
+}
+
+/// Whether the current ratio of `stock` deviates from its goal ratio by at
+/// most its band
+fn within_band(portfolio: &Portfolio, stock: &Stock) -> bool {
+    match (stock.Band, portfolio.drift(stock)) {
+        (Some(band), Some(drift)) => drift.abs() * 100.0 <= band,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn portfolio() -> Portfolio {
+        let stock = |wkn: &str, shares: f64| Stock {
+            WKN: wkn.to_owned(),
+            Price: 10.0,
+            Shares: shares,
+            GoalRatio: 0.5,
+            Band: Some(5.0),
+            ..Default::default()
+        };
+        Portfolio {
+            Stocks: vec![stock("A", 70.0), stock("B", 30.0)],
+            ..Default::default()
+        }
+    }
+
+    fn band_edge_amounts(portfolio: &Portfolio, stocks: &[&Stock], reinvest: f64) -> Vec<f64> {
+        band_edges(portfolio, stocks, reinvest, &ReinvestOptions::default())
+            .into_iter()
+            .map(|(_, amount)| amount)
+            .collect()
+    }
+
+    #[test]
+    fn band_edges_trade_to_the_nearest_edge() {
+        let portfolio = portfolio();
+        let stocks = portfolio.Stocks.iter().collect_vec();
+        // From 70% and 30% to 55% and 45% of 1000
+        assert_eq!(band_edge_amounts(&portfolio, &stocks, 0.0), [-15.0, 15.0]);
+    }
+
+    #[test]
+    fn band_edges_buy_only_what_the_reinvest_amount_pays_for() {
+        let portfolio = portfolio();
+        // 45% of 1100 would be 19.5 new shares of B
+        assert_eq!(
+            band_edge_amounts(&portfolio, &[&portfolio.Stocks[1]], 100.0),
+            [10.0]
+        );
+    }
+
+    #[test]
+    fn band_edges_keep_no_proceeds_idle() {
+        let portfolio = portfolio();
+        // No purchases need the proceeds of selling A
+        assert_eq!(
+            band_edge_amounts(&portfolio, &[&portfolio.Stocks[0]], 0.0),
+            [0.0]
+        );
+    }
 }
@@ -0,0 +1,165 @@
+use crate::credentials::config_file;
+use crate::Error;
+use serde::Deserialize;
+use std::fs::File;
+use std::str::FromStr;
+
+/// Number and currency formatting of the human-readable outputs.
+///
+/// The locale sets the separators and whether the currency symbol goes before
+/// or after the amount, the symbol itself is that of the base currency. The
+/// default formats plain numbers without thousands separators or currency
+/// symbol.
+#[derive(Clone, Debug)]
+pub struct Locale {
+    decimal_separator: char,
+    thousands_separator: Option<char>,
+    /// Whether the currency symbol precedes the amount, `None` for no symbol
+    symbol_first: Option<bool>,
+    currency_symbol: String,
+}
+
+impl Default for Locale {
+    fn default() -> Self {
+        Locale {
+            decimal_separator: '.',
+            thousands_separator: None,
+            symbol_first: None,
+            currency_symbol: String::new(),
+        }
+    }
+}
+
+impl FromStr for Locale {
+    type Err = simple_error::SimpleError;
+
+    fn from_str(tag: &str) -> Result<Self, Self::Err> {
+        let (decimal_separator, thousands_separator, symbol_first) = match tag {
+            "en-US" | "en-GB" => ('.', ',', true),
+            "de-DE" | "de-AT" | "nl-NL" => (',', '.', false),
+            "de-CH" => ('.', '\'', true),
+            "fr-FR" => (',', '\u{202f}', false),
+            _ => return Err(simple_error::simple_error!("Unsupported locale '{}'", tag)),
+        };
+        Ok(Locale {
+            decimal_separator,
+            thousands_separator: Some(thousands_separator),
+            symbol_first: Some(symbol_first),
+            currency_symbol: String::new(),
+        })
+    }
+}
+
+#[derive(Default, Deserialize)]
+struct Config {
+    #[serde(default)]
+    locale: Option<String>,
+}
+
+/// Symbol of the ISO 4217 `currency`, the code itself if it has none
+fn currency_symbol(currency: &str) -> &str {
+    match currency {
+        "EUR" => "€",
+        "USD" => "$",
+        "GBP" => "£",
+        "JPY" => "¥",
+        currency => currency,
+    }
+}
+
+impl Locale {
+    /// The `locale` of the config file, if set.
+    pub fn from_config() -> Result<Option<Self>, Error> {
+        let Some(path) = config_file().filter(|path| path.exists()) else {
+            return Ok(None);
+        };
+        let config: Config = serde_json::from_reader(File::open(&path)?)
+            .map_err(|err| simple_error::simple_error!("Invalid {}: {}", path.display(), err))?;
+        match config.locale {
+            Some(tag) => Ok(Some(tag.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Formats amounts with the symbol of `currency`.
+    pub fn with_currency(self, currency: &str) -> Self {
+        Locale {
+            currency_symbol: currency_symbol(currency).to_owned(),
+            ..self
+        }
+    }
+
+    pub fn number(&self, value: f64, decimals: usize) -> String {
+        let formatted = format!("{:.*}", decimals, value.abs());
+        let (integer, fraction) = formatted.split_once('.').unwrap_or((&formatted, ""));
+
+        let mut grouped = String::new();
+        for (idx, digit) in integer.chars().enumerate() {
+            if idx > 0 && (integer.len() - idx) % 3 == 0 {
+                if let Some(separator) = self.thousands_separator {
+                    grouped.push(separator);
+                }
+            }
+            grouped.push(digit);
+        }
+        if !fraction.is_empty() {
+            grouped.push(self.decimal_separator);
+            grouped.push_str(fraction);
+        }
+
+        match value < 0.0 && formatted.chars().any(|c| c != '0' && c != '.') {
+            true => format!("-{grouped}"),
+            false => grouped,
+        }
+    }
+
+    /// Formats a number of shares with up to 6 decimals for fractional shares.
+    pub fn shares(&self, value: f64) -> String {
+        self.number(value, 6)
+            .trim_end_matches('0')
+            .trim_end_matches(self.decimal_separator)
+            .to_owned()
+    }
+
+    pub fn money(&self, value: f64) -> String {
+        let number = self.number(value.abs(), 2);
+        let sign = match value < 0.0 && number.chars().any(|c| c.is_ascii_digit() && c != '0') {
+            true => "-",
+            false => "",
+        };
+        let symbol = &self.currency_symbol;
+        match self.symbol_first.filter(|_| !symbol.is_empty()) {
+            None => format!("{sign}{number}"),
+            // Codes like CHF are set apart from the amount
+            Some(true) if symbol.chars().all(|c| c.is_ascii_alphabetic()) => {
+                format!("{sign}{symbol} {number}")
+            }
+            Some(true) => format!("{sign}{symbol}{number}"),
+            Some(false) => format!("{sign}{number} {symbol}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locale(tag: &str, currency: &str) -> Locale {
+        tag.parse::<Locale>().unwrap().with_currency(currency)
+    }
+
+    #[test]
+    fn symbol_is_the_base_currency() {
+        assert_eq!(locale("en-US", "EUR").money(1234.5), "€1,234.50");
+        assert_eq!(locale("de-DE", "USD").money(-1234.5), "-1.234,50 $");
+        assert_eq!(locale("de-CH", "CHF").money(1234.5), "CHF 1'234.50");
+    }
+
+    #[test]
+    fn default_has_no_symbol() {
+        assert_eq!(
+            Locale::default().with_currency("EUR").money(1234.5),
+            "1234.50"
+        );
+    }
+}
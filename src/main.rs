@@ -1,37 +1,1208 @@
-use clap::Parser;
-use rebalancing::{calculate_optimal_reinvest, print_reinvest, Error, Portfolio};
-use std::fs::File;
+use clap::{Parser, Subcommand, ValueEnum};
+use rebalancing::accounts::{self, Account};
+use rebalancing::cache::{self, CachedProvider};
+use rebalancing::date;
+use rebalancing::diff;
+use rebalancing::fees::FeeModel;
+use rebalancing::figi;
+use rebalancing::fx;
+use rebalancing::harvest;
+use rebalancing::import::{self, GoalRatios, UsBroker};
+use rebalancing::locale::Locale;
+use rebalancing::money;
+use rebalancing::plan::{Plan, SortOrder};
+use rebalancing::quotes::{self, CoinGecko, PriceProvider, Provider, Quote};
+use rebalancing::report;
+use rebalancing::risk::{self, WeightMethod};
+use rebalancing::savings;
+use rebalancing::schedule;
+use rebalancing::solver::{Objective, ObjectiveWeights, Solver, Stop, MAX_BRANCHES};
+use rebalancing::targets;
+use rebalancing::{
+    calculate_optimal_reinvest, print_allocation_chart, print_reinvest, write_reinvest_markdown,
+    Column, CsvDialect, Error, InfeasibleError, Portfolio, RatioPolicy, ReinvestOptions,
+    TableOptions, Underweight,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Parser, Debug)]
-#[clap(author, version)]
+#[clap(
+    author,
+    version,
+    after_help = "Exit codes: 0 = plan produced, 1 = error, 2 = nothing to do, 3 = infeasible"
+)]
 struct Args {
-    /// Path of portfolio file
-    #[clap(long, default_value = "myPortfolio_sorted.json")]
-    file: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
 
-    /// Amount to reinvest
-    #[clap(long, default_value_t = 10000.0)]
-    reinvest: f64,
+    /// Path of portfolio file, `-` to read from stdin. Can be repeated to merge portfolios
+    #[clap(long, global = true, default_value = "myPortfolio_sorted.json")]
+    file: Vec<String>,
+
+    /// Format of portfolio file, detected from content and file extension if omitted
+    #[clap(long, global = true, value_enum, alias = "format")]
+    input_format: Option<InputFormat>,
+
+    /// Path of JSON file with goal ratios by ISIN/WKN, required for broker exports
+    #[clap(long, global = true)]
+    goal_ratios: Option<String>,
+
+    /// Path of JSON file with a target allocation tree, e.g. asset classes split
+    /// into regions split into stocks, overriding the goal ratios
+    #[clap(long, global = true)]
+    targets: Option<String>,
+
+    /// Delimiter of CSV portfolio files
+    #[clap(long, global = true, default_value_t = ',')]
+    csv_delimiter: char,
+
+    /// Use decimal commas in CSV portfolio files, e.g. `1.234,56`
+    #[clap(long, global = true, action)]
+    decimal_comma: bool,
+
+    /// Cash available for purchases in an account as `NAME=AMOUNT` in the base
+    /// currency, can be repeated. Splits the orders across the accounts of the
+    /// portfolio files, named by their `Account` or path, and reinvests the
+    /// total cash.
+    #[clap(
+        long,
+        value_name = "NAME=AMOUNT",
+        value_parser = parse_account_cash,
+        conflicts_with_all = ["reinvest", "sell_to_rebalance"]
+    )]
+    account_cash: Vec<(String, f64)>,
+
+    /// Amount to reinvest, or percent of the current portfolio value like `5%`. Defaults to the
+    /// free `Cash` of the portfolio, or 10000 without any
+    #[clap(long, value_parser = parse_reinvest)]
+    reinvest: Option<ReinvestAmount>,
 
     /// Prohibit selling of stocks
     #[clap(long, action)]
     no_selling: bool,
+
+    /// How to treat goal ratios not summing up to 1
+    #[clap(long, value_enum, default_value_t = RatioPolicy::default())]
+    ratio_policy: RatioPolicy,
+
+    /// Goal ratio of cash on the scale of the goal ratios, overriding `CashGoalRatio` of the
+    /// portfolio file
+    #[clap(long)]
+    cash_goal_ratio: Option<f64>,
+
+    /// Never buy the stock with this WKN, can be repeated
+    #[clap(long, value_name = "WKN")]
+    no_buy: Vec<String>,
+
+    /// Never sell the stock with this WKN, can be repeated
+    #[clap(long, value_name = "WKN")]
+    no_sell: Vec<String>,
+
+    /// Rebalance without new money, paying every purchase with the proceeds of sales
+    #[clap(long, action, conflicts_with_all = ["no_selling", "reinvest"])]
+    sell_to_rebalance: bool,
+
+    /// Algorithm for rounding the fractional amounts to whole shares
+    #[clap(long, value_enum, default_value_t = Solver::default())]
+    solver: Solver,
+
+    /// Round greedily by drift instead of optimally, same as `--solver greedy`
+    #[clap(long, action, conflicts_with = "solver")]
+    heuristic: bool,
+
+    /// Buy and sell fractional shares of stocks without a `Fractional` of their own, rounded to
+    /// the `ShareDecimals` of a stock if given
+    #[clap(long, action)]
+    fractional: bool,
+
+    /// Order fees paid from the reinvest amount as `FIXED`, `RATE%` or `FIXED+RATE%`,
+    /// e.g. `4.90+0.25%`, or the name of a broker in `fees` of the config file
+    #[clap(long)]
+    fees: Option<String>,
+
+    /// Amount of investment an order has to be worth to be placed, preferring fewer orders
+    /// over a slightly higher invested amount
+    #[clap(long, default_value_t = 0.0)]
+    trade_penalty: f64,
+
+    /// Only trade the N stocks furthest below their goal ratio
+    #[clap(long, value_name = "N")]
+    max_trades: Option<usize>,
+
+    /// Percentage points of drift from the goal ratios up to which no plan is made, exiting
+    /// with code 2 if every stock is within them
+    #[clap(long, value_name = "PERCENT")]
+    rebalance_threshold: Option<f64>,
+
+    /// Tax rate on realized gains of sales, e.g. 0.26375, computed from the `Lots` of a stock
+    #[clap(long, default_value_t = 0.0)]
+    tax_rate: f64,
+
+    /// Value a single order may have at most, spreading the rest of the reinvest amount across
+    /// other stocks
+    #[clap(long, value_name = "AMOUNT")]
+    max_order: Option<f64>,
+
+    /// Fraction of the shares of a stock with a goal ratio of 0 to sell at most per run, e.g. 0.25
+    /// to exit it over four runs
+    #[clap(long, value_name = "FRACTION")]
+    wind_down: Option<f64>,
+
+    /// Slippage in percent of the price of stocks without a `Slippage`, e.g. half the bid/ask
+    /// spread
+    #[clap(long, value_name = "PERCENT")]
+    slippage: Option<f64>,
+
+    /// Seconds after which the solver settles for the best rounding found so far, reporting how
+    /// much less it invests than the fractional optimum
+    #[clap(long, value_name = "SECONDS")]
+    time_limit: Option<f64>,
+
+    /// Close the gaps of the most underweight stocks one after another when the purchases cannot
+    /// close all of them, instead of spreading small amounts that round down to no shares
+    #[clap(long, value_enum, value_name = "PRIORITY")]
+    underweight_first: Option<Underweight>,
+
+    /// Never sell shares of a lot bought above the current price, see `Lots`
+    #[clap(long, action)]
+    no_loss_sales: bool,
+
+    /// Net gain the sales may realize at most, e.g. the remaining tax allowance of the year
+    #[clap(long, value_name = "AMOUNT")]
+    max_realized_gain: Option<f64>,
+
+    /// Band of stocks without a `Band` as `ABSOLUTE/RELATIVE`, e.g. `5/25` for the smaller of 5
+    /// percentage points and 25% of the goal ratio
+    #[clap(long, value_name = "ABSOLUTE/RELATIVE", value_parser = parse_band_rule)]
+    band_rule: Option<(f64, f64)>,
+
+    /// Trade stocks outside of their band only back to its nearest edge instead of the goal ratio
+    #[clap(long, action)]
+    to_band_edge: bool,
+
+    /// Fee an order may cost at most as fraction of its value, e.g. 0.01, dropping smaller orders
+    /// and spreading their amount across the other stocks
+    #[clap(long, value_name = "RATIO")]
+    max_fee_ratio: Option<f64>,
+
+    /// Plan N months of contributions of --reinvest each at current prices, printing the
+    /// orders of every month
+    #[clap(long, value_name = "N", conflicts_with_all = ["apply", "sell_to_rebalance"])]
+    months: Option<usize>,
+
+    /// Distribute --reinvest as recurring amount across savings plans instead of buying shares
+    #[clap(long, action, conflicts_with_all = ["apply", "months", "sell_to_rebalance"])]
+    savings_plan: bool,
+
+    /// Smallest rate of a savings plan the broker accepts
+    #[clap(long, value_name = "AMOUNT", default_value_t = 25.0)]
+    plan_minimum: f64,
+
+    /// Suggest selling the oldest `Lots` of stocks with an unrealized loss of at least this
+    /// amount, replaced by their `Alternative`
+    #[clap(long, value_name = "MIN_LOSS")]
+    harvest_losses: Option<f64>,
+
+    /// What the rounding to whole shares optimizes for
+    #[clap(long, value_enum, default_value_t = Objective::default())]
+    objective: Objective,
+
+    /// Weights of the `weighted` objective as `TERM=WEIGHT` pairs of `invested`, `drift`,
+    /// `trades` and `fees`, e.g. `invested=1,drift=0.5,trades=2`, or the name of a preset in
+    /// `objectives` of the config file. Implies `--objective weighted`
+    #[clap(long, value_name = "WEIGHTS")]
+    objective_weights: Option<String>,
+
+    /// Update prices with current quotes by `Symbol` before optimizing, empty symbols are
+    /// resolved by ISIN or WKN with OpenFIGI.
+    /// Lowercase symbols like `bitcoin` are looked up as CoinGecko coin ids first
+    #[clap(long, action)]
+    fetch_prices: bool,
+
+    /// Path of a quotes file written by `fetch --prices-file` to take prices from
+    #[clap(long)]
+    prices: Option<String>,
+
+    /// Override the price of a stock as `WKN=PRICE` in the currency of the stock, can be repeated
+    #[clap(long = "price", value_name = "WKN=PRICE", value_parser = parse_price_override)]
+    price_overrides: Vec<(String, f64)>,
+
+    /// Refuse network access, use cached quotes and exchange rates and warn about the age of every price
+    #[clap(long, global = true, action)]
+    offline: bool,
+
+    /// Seconds for which fetched quotes are reused from the cache, 0 to always fetch
+    #[clap(long, global = true, default_value_t = 900)]
+    price_cache_ttl: u64,
+
+    /// Currency to convert prices of stocks with another `Currency` to with ECB reference rates,
+    /// the `BaseCurrency` of the portfolio or EUR if omitted
+    #[clap(long, global = true)]
+    base_currency: Option<String>,
+
+    /// Relative price change of fetched quotes against the portfolio file regarded as a mixup
+    #[clap(long, global = true, default_value_t = 0.3)]
+    max_price_move: f64,
+
+    /// Use fetched quotes even if they moved by more than --max-price-move
+    #[clap(long, global = true, action)]
+    force: bool,
+
+    /// Source of quotes for --fetch-prices and fetch
+    #[clap(long, global = true, value_enum, default_value_t = Provider::default())]
+    provider: Provider,
+
+    /// Fetch dividends to show the expected dividends with the shares after the plan
+    #[clap(long, action)]
+    dividends: bool,
+
+    /// Output format of the rebalancing plan
+    #[clap(long, value_enum, default_value_t = OutputFormat::Table)]
+    output: OutputFormat,
+
+    /// Path to write the orders as Portfolio Performance transaction CSV to
+    #[clap(long)]
+    pp_transactions: Option<String>,
+
+    /// Date of the Portfolio Performance transactions as YYYY-MM-DD, today if omitted
+    #[clap(long)]
+    transaction_date: Option<String>,
+
+    /// Format of log messages on stderr, log level is set with RUST_LOG and defaults to warn
+    #[clap(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Write the shares after the plan back to the portfolio file, keeping a `.bak` backup
+    #[clap(long, action)]
+    apply: bool,
+
+    /// Suppress the table and only emit machine-readable output, JSON unless --output is given
+    #[clap(long, action)]
+    quiet: bool,
+
+    /// Path to write the plan to instead of stdout, not supported for tables
+    #[clap(long)]
+    output_file: Option<String>,
+
+    /// Columns of the table
+    #[clap(long, value_enum, value_delimiter = ',', default_values_t = Column::ALL.to_vec())]
+    columns: Vec<Column>,
+
+    /// Order of stocks in the plan, portfolio order if omitted
+    #[clap(long, value_enum)]
+    sort: Option<SortOrder>,
+
+    /// Disable coloring of the table by drift from the goal ratio
+    #[clap(long, action)]
+    no_color: bool,
+
+    /// Absolute ratio deviation up to which a stock is colored as on target
+    #[clap(long, default_value_t = 0.01)]
+    drift_tolerance: f64,
+
+    /// Print a bar chart of goal and actual allocation before and after the plan
+    #[clap(long, action)]
+    chart: bool,
+
+    /// Locale for formatting numbers and amounts, e.g. `de-DE`, defaults to `locale` of the config file
+    #[clap(long)]
+    locale: Option<Locale>,
+
+    /// Path to write a report of the plan to, PDF for `.pdf` paths and HTML otherwise
+    #[clap(long)]
+    report: Option<String>,
+
+    /// Directory to write allocation and drift charts as SVG files to
+    #[clap(long)]
+    charts_dir: Option<String>,
+
+    /// Path to write a `symbol,side,quantity,limit,currency` order CSV for broker import to
+    #[clap(long)]
+    orders: Option<String>,
+}
+
+/// Value of --reinvest
+#[derive(Clone, Copy, Debug)]
+enum ReinvestAmount {
+    Absolute(f64),
+    /// Percent of the current portfolio value
+    Percent(f64),
 }
 
-fn main() -> Result<(), Error> {
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    Table,
+    Json,
+    Csv,
+    Markdown,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Convert the portfolio into another format
+    Convert {
+        /// Path of converted portfolio file, `-` to write to stdout
+        #[clap(long, default_value = "-")]
+        output: String,
+
+        /// Format of converted portfolio, guessed from the output extension if omitted
+        #[clap(long, value_enum)]
+        to: Option<PortfolioFormat>,
+    },
+    /// Update the prices in the portfolio file with current quotes
+    Fetch {
+        /// Path to write the quotes by WKN to as JSON instead of updating the portfolio file
+        #[clap(long)]
+        prices_file: Option<String>,
+    },
+    /// Download daily prices of all stocks with a symbol as CSV
+    History {
+        /// First day as YYYY-MM-DD
+        #[clap(long)]
+        from: String,
+
+        /// Last day as YYYY-MM-DD, today if omitted
+        #[clap(long)]
+        to: Option<String>,
+
+        /// Path of the CSV file, `-` to write to stdout
+        #[clap(long, default_value = "-")]
+        output: String,
+    },
+    /// Set the goal ratios in the portfolio file from the risk of the daily prices
+    Weights {
+        /// How to weight the stocks
+        #[clap(long, value_enum, default_value_t = WeightMethod::default())]
+        method: WeightMethod,
+
+        /// First day as YYYY-MM-DD
+        #[clap(long)]
+        from: String,
+
+        /// Last day as YYYY-MM-DD, today if omitted
+        #[clap(long)]
+        to: Option<String>,
+    },
+    /// Set the goal ratios in the portfolio file proportional to the market caps
+    MarketCaps {
+        /// Keep only the largest stocks, setting the goal ratio of the others to 0
+        #[clap(long)]
+        top: Option<usize>,
+    },
+    /// Compare two portfolio files, e.g. to reconcile executed orders with the plan
+    Diff {
+        /// Path of the old portfolio file
+        old: String,
+
+        /// Path of the new portfolio file
+        new: String,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PortfolioFormat {
+    Json,
+    Csv,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum InputFormat {
+    Json,
+    Csv,
+    /// DKB Depotübersicht CSV export
+    Dkb,
+    /// comdirect Depotübersicht CSV export
+    Comdirect,
+    /// Interactive Brokers Flex Query XML report
+    IbkrFlex,
+    /// Degiro Portfolio.csv export
+    Degiro,
+    /// Ghostfolio account export
+    Ghostfolio,
+    /// beancount or hledger journal
+    Ledger,
+    /// Fidelity Portfolio_Positions.csv export
+    Fidelity,
+    /// Charles Schwab positions CSV export
+    Schwab,
+    /// Vanguard holdings CSV download
+    Vanguard,
+}
+
+impl InputFormat {
+    fn detect(path: &str, content: &[u8]) -> Self {
+        let head = String::from_utf8_lossy(&content[..content.len().min(4096)]);
+        let head = head.trim_start();
+
+        if head.starts_with('<') && head.contains("<FlexQueryResponse") {
+            InputFormat::IbkrFlex
+        } else if head.starts_with('{') && head.contains("\"activities\"") {
+            InputFormat::Ghostfolio
+        } else if head.starts_with('{') {
+            InputFormat::Json
+        } else if head.contains("ISIN / WKN") {
+            InputFormat::Dkb
+        } else if head.contains("Akt. Kurs") {
+            InputFormat::Comdirect
+        } else if head.contains("Symbol/ISIN") {
+            InputFormat::Degiro
+        } else if head.contains("Account Name") && head.contains("Last Price") {
+            InputFormat::Fidelity
+        } else if head.starts_with("\"Positions for account") {
+            InputFormat::Schwab
+        } else if head.contains("Investment Name") && head.contains("Share Price") {
+            InputFormat::Vanguard
+        } else {
+            match Path::new(path).extension().and_then(|e| e.to_str()) {
+                Some("csv") => InputFormat::Csv,
+                Some("beancount" | "ledger" | "journal" | "hledger") => InputFormat::Ledger,
+                _ => InputFormat::Json,
+            }
+        }
+    }
+}
+
+const EXIT_NOTHING_TO_DO: u8 = 2;
+const EXIT_INFEASIBLE: u8 = 3;
+
+/// Reinvest amount of a portfolio without cash if --reinvest is omitted
+const DEFAULT_REINVEST: f64 = 10000.0;
+
+fn main() -> ExitCode {
     let args = Args::parse();
 
-    env_logger::builder()
-        .format_timestamp(Some(env_logger::TimestampPrecision::Millis))
-        .init();
+    let mut logger =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    logger.format_timestamp(Some(env_logger::TimestampPrecision::Millis));
+    if let LogFormat::Json = args.log_format {
+        logger.format(|buf, record| {
+            let line = serde_json::json!({
+                "timestamp": buf.timestamp_millis().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    }
+    logger.init();
+
+    match run(&args) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            match err.downcast_ref::<InfeasibleError>() {
+                Some(_) => ExitCode::from(EXIT_INFEASIBLE),
+                None => ExitCode::FAILURE,
+            }
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<ExitCode, Error> {
+    if let Some(Command::Diff { old, new }) = &args.command {
+        let diffs = diff::diff_portfolios(&load_portfolio(old, args)?, &load_portfolio(new, args)?);
+        match (args.quiet, args.output) {
+            (true, _) | (_, OutputFormat::Json) => {
+                println!("{}", serde_json::to_string_pretty(&diffs)?)
+            }
+            _ => diff::print_diff(&diffs),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let portfolios = args
+        .file
+        .iter()
+        .map(|path| load_portfolio(path, args))
+        .collect::<Result<Vec<_>, Error>>()?;
+    let accounts = match args.account_cash.is_empty() {
+        true => Vec::new(),
+        false => load_accounts(&portfolios, args)?,
+    };
+    let mut portfolio = Portfolio::merge(portfolios)?;
+    // Written back by fetch and --apply without the overrides of this run
+    let loaded = portfolio.clone();
+    let base_currency = args
+        .base_currency
+        .clone()
+        .or_else(|| portfolio.BaseCurrency.clone())
+        .unwrap_or_else(|| "EUR".to_owned());
+    let locale = match args.locale.clone() {
+        Some(locale) => locale,
+        None => Locale::from_config()?.unwrap_or_default(),
+    }
+    .with_currency(&base_currency);
+    if let Some(path) = &args.targets {
+        targets::apply_targets(&mut portfolio, &targets::read_targets(File::open(path)?)?)?;
+    }
+
+    if let Some(Command::Convert { output, to }) = &args.command {
+        convert(&portfolio, output, *to, &csv_dialect(args)?)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    quotes::set_offline(args.offline);
+    if let Some(Command::History { from, to, output }) = &args.command {
+        resolve_symbols(&mut portfolio, args);
+        let to = to.clone().unwrap_or_else(date::today);
+        let writer: Box<dyn Write> = match output.as_str() {
+            "-" => Box::new(io::stdout().lock()),
+            path => Box::new(File::create(path)?),
+        };
+        quotes::write_history(
+            writer,
+            &portfolio,
+            &cached(args.provider.build()?, args)?,
+            from,
+            &to,
+        )?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::Weights { method, from, to }) = &args.command {
+        resolve_symbols(&mut portfolio, args);
+        let to = to.clone().unwrap_or_else(date::today);
+        let provider = cached(args.provider.build()?, args)?;
+        risk::apply_risk_weights(&mut portfolio, &provider, from, &to, *method)?;
+        write_portfolio_file(&portfolio, args, "weights")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(Command::MarketCaps { top }) = &args.command {
+        resolve_symbols(&mut portfolio, args);
+        let provider = cached(args.provider.build()?, args)?;
+        quotes::apply_market_cap_weights(&mut portfolio, &provider, *top)?;
+        write_portfolio_file(&portfolio, args, "market-caps")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let mut updated_at = HashMap::new();
+    if let Some(path) = &args.prices {
+        let quotes: HashMap<String, Quote> = serde_json::from_reader(File::open(path)?)?;
+        quotes::apply_quotes(&mut portfolio, &quotes);
+        updated_at.extend(timestamps(&quotes));
+    }
+    if args.fetch_prices || matches!(args.command, Some(Command::Fetch { .. })) {
+        resolve_symbols(&mut portfolio, args);
+        let coins = Box::new(CoinGecko {
+            currency: base_currency.clone(),
+        });
+        let quotes = quotes::fetch_quotes(
+            &portfolio,
+            &cached(args.provider.build()?, args)?,
+            &cached(coins, args)?,
+        )?;
+        let moves = quotes::suspicious_moves(&portfolio, &quotes, args.max_price_move);
+        if !moves.is_empty() && !args.force {
+            let lines: Vec<String> = moves
+                .iter()
+                .map(|(wkn, old, new)| {
+                    format!(
+                        "  {wkn} {old} -> {new} ({:+.0}%)",
+                        (new / old - 1.0) * 100.0
+                    )
+                })
+                .collect();
+            simple_error::bail!(
+                "Fetched prices moved by more than {:.0}%, check symbols and currencies or use --force:\n{}",
+                args.max_price_move * 100.0,
+                lines.join("\n")
+            );
+        }
+        quotes::apply_quotes(&mut portfolio, &quotes);
+        updated_at.extend(timestamps(&quotes));
+
+        if let Some(Command::Fetch { prices_file }) = &args.command {
+            match prices_file {
+                Some(path) => write_atomically(path, |temporary| {
+                    let mut writer = File::create(temporary)?;
+                    let sorted: BTreeMap<_, _> = quotes.iter().collect();
+                    serde_json::to_writer_pretty(&mut writer, &sorted)?;
+                    writeln!(writer)?;
+                    Ok(())
+                })?,
+                None => {
+                    // Refreshes the portfolio as loaded, keeping its goal
+                    // ratios even with --targets
+                    let mut refreshed = loaded;
+                    for stock in refreshed.Stocks.iter_mut() {
+                        if let Some(resolved) = portfolio.Stocks.iter().find(|s| s.WKN == stock.WKN)
+                        {
+                            stock.Symbol = resolved.Symbol.clone();
+                        }
+                    }
+                    quotes::apply_quotes(&mut refreshed, &quotes);
+                    write_portfolio_file(&refreshed, args, "fetch")?
+                }
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+    }
+    for (wkn, price) in args.price_overrides.iter() {
+        let Some(stock) = portfolio.Stocks.iter_mut().find(|s| &s.WKN == wkn) else {
+            simple_error::bail!("No stock with WKN {} to override the price of", wkn);
+        };
+        log::info!("Overriding price of {wkn} from {} to {price}", stock.Price);
+        stock.Price = *price;
+        updated_at.insert(
+            wkn.clone(),
+            SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        );
+    }
+    if args.offline {
+        warn_price_ages(&portfolio, &updated_at, args);
+    }
+    for (wkns, no_buy) in [(&args.no_buy, true), (&args.no_sell, false)] {
+        for wkn in wkns.iter() {
+            let Some(stock) = portfolio.Stocks.iter_mut().find(|s| &s.WKN == wkn) else {
+                simple_error::bail!("No stock with WKN {} to lock", wkn);
+            };
+            match no_buy {
+                true => stock.NoBuy = true,
+                false => stock.NoSell = true,
+            }
+        }
+    }
+    if let Some(ratio) = args.cash_goal_ratio {
+        portfolio.CashGoalRatio = Some(ratio);
+    }
+    portfolio.normalize_goal_ratios(args.ratio_policy)?;
+    if let Some(percent) = args.slippage {
+        for stock in portfolio.Stocks.iter_mut() {
+            stock.Slippage.get_or_insert(percent);
+        }
+    }
+    if let Some((absolute, relative)) = args.band_rule {
+        portfolio.apply_band_rule(absolute, relative);
+    }
+    let native_portfolio = portfolio.clone();
+    let mut fx_rates = fx::FxRates::new();
+    if portfolio
+        .Stocks
+        .iter()
+        .any(|s| !s.Currency.is_empty() && s.Currency != base_currency)
+        || portfolio
+            .Cash
+            .keys()
+            .any(|currency| *currency != base_currency)
+    {
+        fx_rates = cache::ecb_rates()?;
+        fx::convert_to_base(&mut portfolio, &base_currency, &fx_rates)?;
+    }
+
+    if let Some(threshold) = args.rebalance_threshold {
+        // An empty portfolio always needs the reinvest amount invested
+        let max_drift = portfolio
+            .Stocks
+            .iter()
+            .map(|stock| portfolio.drift(stock).map(f64::abs))
+            .try_fold(0.0, |max: f64, drift| Some(max.max(drift?)));
+        if let Some(max_drift) = max_drift.filter(|max_drift| max_drift * 100.0 <= threshold) {
+            log::warn!(
+                "No rebalance needed, the largest drift of {:.2} percentage points is within {threshold}",
+                max_drift * 100.0
+            );
+            return Ok(ExitCode::from(EXIT_NOTHING_TO_DO));
+        }
+    }
+
+    let solver = match args.heuristic {
+        true => Solver::Greedy,
+        false => args.solver,
+    };
+    if args.output_file.is_some() && !args.quiet && matches!(args.output, OutputFormat::Table) {
+        simple_error::bail!(
+            "--output-file cannot write tables, use --output json, csv or markdown"
+        );
+    }
+    if args.max_trades == Some(0) {
+        simple_error::bail!("--max-trades needs at least one trade");
+    }
+    let fees = match &args.fees {
+        Some(spec) => FeeModel::from_spec(spec)?,
+        None => FeeModel::default(),
+    };
+    if let Some(ratio) = args.max_fee_ratio {
+        if fees.min_efficient_order(ratio).is_infinite() {
+            simple_error::bail!(
+                "The fee rate {} exceeds --max-fee-ratio {}",
+                fees.rate,
+                ratio
+            );
+        }
+    }
+    let (objective, weights) = match (&args.objective_weights, args.objective) {
+        (Some(spec), Objective::Invested | Objective::Weighted) => {
+            (Objective::Weighted, ObjectiveWeights::from_spec(spec)?)
+        }
+        (Some(_), objective) => {
+            simple_error::bail!(
+                "--objective-weights needs the weighted objective, not {:?}",
+                objective
+            )
+        }
+        (None, Objective::Weighted) => {
+            simple_error::bail!("--objective weighted needs --objective-weights")
+        }
+        (None, objective) => (objective, ObjectiveWeights::default()),
+    };
+    let options = ReinvestOptions {
+        no_selling: args.no_selling,
+        solver,
+        fractional: args.fractional,
+        fees,
+        trade_penalty: args.trade_penalty,
+        max_trades: args.max_trades,
+        objective,
+        weights,
+        tax_rate: args.tax_rate,
+        max_order: args.max_order,
+        max_fee_ratio: args.max_fee_ratio,
+        to_band_edge: args.to_band_edge,
+        wind_down: args.wind_down,
+        no_loss_sales: args.no_loss_sales,
+        max_realized_gain: args.max_realized_gain,
+        time_limit: args.time_limit.map(Duration::from_secs_f64),
+        underweight_first: args.underweight_first,
+    };
+    let reinvest = match (args.sell_to_rebalance, args.reinvest) {
+        _ if !accounts.is_empty() => accounts.iter().map(|a| a.cash).sum(),
+        (true, _) => 0.0,
+        (false, None) if !portfolio.Cash.is_empty() => portfolio.free_cash(),
+        (false, None) => DEFAULT_REINVEST,
+        (false, Some(ReinvestAmount::Absolute(amount))) => amount,
+        (false, Some(ReinvestAmount::Percent(percent))) => {
+            let value: f64 = portfolio.Stocks.iter().map(|s| s.Price * s.Shares).sum();
+            let amount = money::round_cents(value * percent / 100.0);
+            log::info!("Reinvesting {percent}% of the portfolio value {value:.2}: {amount:.2}");
+            amount
+        }
+    };
+    if args.savings_plan {
+        let rates = savings::savings_plan(&portfolio, reinvest, args.plan_minimum, &options);
+        match (args.quiet, args.output) {
+            (true, _) | (_, OutputFormat::Json) => {
+                println!("{}", serde_json::to_string_pretty(&rates)?)
+            }
+            (_, OutputFormat::Table) => savings::print_savings_plan(&rates, &locale),
+            (_, output) => {
+                simple_error::bail!("--savings-plan does not support {:?} output", output)
+            }
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(months) = args.months {
+        let schedule = schedule::plan_months(&portfolio, reinvest, months, &options)?;
+        match (args.quiet, args.output) {
+            (true, _) | (_, OutputFormat::Json) => {
+                println!("{}", serde_json::to_string_pretty(&schedule)?)
+            }
+            (_, OutputFormat::Table) => schedule::print_schedule(&schedule, &portfolio, &locale),
+            (_, output) => simple_error::bail!("--months does not support {:?} output", output),
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    let (optimal_reinvest, new_amounts_map, stop) =
+        calculate_optimal_reinvest(&portfolio, reinvest, &options)?;
+    match stop {
+        Some(Stop::TimeLimit) => {
+            log::warn!("{solver:?} search stopped at the time limit with the best rounding found")
+        }
+        Some(Stop::BranchLimit) => log::warn!(
+            "{solver:?} search stopped after {MAX_BRANCHES} branches with the best rounding found"
+        ),
+        None => {}
+    }
+    if stop.is_some() || matches!(solver, Solver::Greedy | Solver::Nearest) {
+        // The fractional amounts invest exactly the reinvest amount
+        let gap = reinvest - optimal_reinvest;
+        match reinvest == 0.0 {
+            true => {
+                log::warn!("{solver:?} rounding invests {gap:.2} less than the fractional optimum")
+            }
+            false => log::warn!(
+                "{solver:?} rounding invests {gap:.2} less than the fractional optimum ({:.3}%)",
+                gap / reinvest * 100.0
+            ),
+        }
+    }
+
+    let mut plan = Plan::new(&portfolio, &new_amounts_map, optimal_reinvest, reinvest);
+    plan.apply_native_prices(&native_portfolio, &base_currency);
+    plan.apply_fees(&fees);
+    plan.apply_slippage(&portfolio);
+    plan.apply_taxes(&portfolio, args.tax_rate);
+
+    if args.apply {
+        // Lots of purchases are taken at the native prices of this run
+        let mut traded = native_portfolio;
+        traded.apply(&new_amounts_map);
+        let mut updated = loaded;
+        for stock in updated.Stocks.iter_mut() {
+            if let Some(traded) = traded.Stocks.iter().find(|s| s.WKN == stock.WKN) {
+                stock.Shares = traded.Shares;
+                stock.Lots = traded.Lots.clone();
+            }
+        }
+        if !updated.Cash.is_empty() {
+            fx::withdraw_in_base(
+                &mut updated,
+                &base_currency,
+                plan.Cash - plan.new_cash(),
+                &fx_rates,
+            )?;
+        }
+        apply_plan(&updated, args)?;
+    }
+    if args.sell_to_rebalance {
+        plan.pair_orders();
+    }
+    if !accounts.is_empty() {
+        plan.Placements = accounts::place_orders(&portfolio, &plan, &accounts, args.fractional);
+    }
+    if let Some(min_loss) = args.harvest_losses {
+        plan.Harvests = harvest::harvest_losses(&portfolio, &plan, min_loss, args.tax_rate);
+    }
+    if let Some(order) = args.sort {
+        plan.sort(order);
+    }
+    if args.dividends {
+        let provider = cached(args.provider.build()?, args)?;
+        plan.Dividends = Some(quotes::forecast_dividends(
+            &plan,
+            &provider,
+            &base_currency,
+            &fx_rates,
+        ));
+    }
+
+    if let Some(path) = &args.report {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("pdf") => report::write_pdf_report(File::create(path)?, &plan, &locale)?,
+            _ => report::write_html_report(File::create(path)?, &plan, &locale)?,
+        }
+    }
+    if let Some(directory) = &args.charts_dir {
+        report::write_svg_charts(Path::new(directory), &plan)?;
+    }
+    if let Some(path) = &args.orders {
+        plan.to_order_csv_writer(File::create(path)?, &base_currency)?;
+    }
+    if let Some(path) = &args.pp_transactions {
+        let date = args.transaction_date.clone().unwrap_or_else(date::today);
+        plan.to_portfolio_performance_writer(File::create(path)?, &date, &base_currency)?;
+    }
+
+    let mut writer: Box<dyn Write> = match &args.output_file {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout().lock()),
+    };
+    if args.chart && !args.quiet {
+        print_allocation_chart(&plan);
+    }
+
+    let output = match (args.quiet, args.output) {
+        (true, OutputFormat::Table) => OutputFormat::Json,
+        (_, output) => output,
+    };
+    match output {
+        OutputFormat::Table => print_reinvest(
+            &plan,
+            &TableOptions {
+                columns: args.columns.clone(),
+                color: !args.no_color,
+                drift_tolerance: args.drift_tolerance,
+                locale: locale.clone(),
+            },
+        ),
+        OutputFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &plan)?;
+            writeln!(writer)?;
+        }
+        OutputFormat::Csv => plan.to_csv_writer(writer)?,
+        OutputFormat::Markdown => write_reinvest_markdown(writer, &plan, &locale)?,
+    }
+
+    match plan.Stocks.iter().all(|entry| entry.NewShares == 0.0) {
+        true => Ok(ExitCode::from(EXIT_NOTHING_TO_DO)),
+        false => Ok(ExitCode::SUCCESS),
+    }
+}
 
-    let portfolio_file = File::open(args.file)?;
-    let portfolio: Portfolio = serde_json::from_reader(portfolio_file)?;
+fn convert(
+    portfolio: &Portfolio,
+    output: &str,
+    to: Option<PortfolioFormat>,
+    dialect: &CsvDialect,
+) -> Result<(), Error> {
+    let format =
+        to.unwrap_or_else(
+            || match Path::new(output).extension().and_then(|e| e.to_str()) {
+                Some("csv") => PortfolioFormat::Csv,
+                _ => PortfolioFormat::Json,
+            },
+        );
+    let mut writer: Box<dyn Write> = match output {
+        "-" => Box::new(io::stdout().lock()),
+        path => Box::new(File::create(path)?),
+    };
 
-    let (optimal_reinvest, new_amounts_map) =
-        calculate_optimal_reinvest(&portfolio, args.reinvest, args.no_selling)?;
+    match format {
+        PortfolioFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, portfolio)?;
+            writeln!(writer)?;
+        }
+        PortfolioFormat::Csv => portfolio.to_csv_writer_with_dialect(writer, dialect)?,
+    }
+    Ok(())
+}
+
+fn apply_plan(updated: &Portfolio, args: &Args) -> Result<(), Error> {
+    if let [path] = args.file.as_slice() {
+        let backup = format!("{path}.bak");
+        fs::copy(path, &backup)?;
+        log::info!("Backed up {path} to {backup}");
+    }
+    write_portfolio_file(updated, args, "--apply")
+}
 
-    print_reinvest(&portfolio, &new_amounts_map, optimal_reinvest);
+/// Writes `portfolio` back to the single JSON or CSV portfolio file for the
+/// option or command `requester`.
+fn write_portfolio_file(portfolio: &Portfolio, args: &Args, requester: &str) -> Result<(), Error> {
+    let [path] = args.file.as_slice() else {
+        simple_error::bail!("{} needs exactly one portfolio file", requester);
+    };
+    if path == "-" {
+        simple_error::bail!("{} cannot write back to stdin", requester);
+    }
+    let format = match args
+        .input_format
+        .unwrap_or_else(|| InputFormat::detect(path, &fs::read(path).unwrap_or_default()))
+    {
+        InputFormat::Json => PortfolioFormat::Json,
+        InputFormat::Csv => PortfolioFormat::Csv,
+        format => simple_error::bail!("{} cannot write {:?} files", requester, format),
+    };
 
+    write_atomically(path, |temporary| {
+        convert(portfolio, temporary, Some(format), &csv_dialect(args)?)
+    })
+}
+
+/// Writes to a temporary file next to `path` and renames it to `path`, so
+/// `path` is never left partially written.
+fn write_atomically(
+    path: &str,
+    write: impl FnOnce(&str) -> Result<(), Error>,
+) -> Result<(), Error> {
+    let temporary = format!("{path}.tmp");
+    write(&temporary)?;
+    fs::rename(&temporary, path)?;
     Ok(())
 }
+
+/// Unix timestamps of the quotes by WKN, where known
+fn timestamps(quotes: &HashMap<String, Quote>) -> impl Iterator<Item = (String, u64)> + '_ {
+    quotes
+        .iter()
+        .filter_map(|(wkn, quote)| Some((wkn.clone(), quote.updated?)))
+}
+
+fn parse_reinvest(value: &str) -> Result<ReinvestAmount, String> {
+    let parse = |number: &str| {
+        number
+            .trim()
+            .parse::<f64>()
+            .map_err(|err| format!("invalid amount '{value}': {err}"))
+    };
+    match value.strip_suffix('%') {
+        Some(percent) => Ok(ReinvestAmount::Percent(parse(percent)?)),
+        None => Ok(ReinvestAmount::Absolute(parse(value)?)),
+    }
+}
+
+fn parse_band_rule(value: &str) -> Result<(f64, f64), String> {
+    let parse = |number: &str| {
+        number
+            .trim()
+            .parse::<f64>()
+            .map_err(|err| format!("invalid band '{number}': {err}"))
+    };
+    let (absolute, relative) = value
+        .split_once('/')
+        .ok_or_else(|| format!("expected ABSOLUTE/RELATIVE, got '{value}'"))?;
+    Ok((parse(absolute)?, parse(relative)?))
+}
+
+fn parse_account_cash(value: &str) -> Result<(String, f64), String> {
+    let (name, amount) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=AMOUNT, got '{value}'"))?;
+    let amount: f64 = amount
+        .parse()
+        .map_err(|err| format!("invalid amount '{amount}': {err}"))?;
+    if amount < 0.0 {
+        return Err(format!("cash of {name} cannot be negative"));
+    }
+    Ok((name.to_owned(), amount))
+}
+
+fn parse_price_override(value: &str) -> Result<(String, f64), String> {
+    let (wkn, price) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected WKN=PRICE, got '{value}'"))?;
+    let price: f64 = price
+        .parse()
+        .map_err(|err| format!("invalid price '{price}': {err}"))?;
+    if price <= 0.0 {
+        return Err(format!("price of {wkn} has to be positive"));
+    }
+    Ok((wkn.to_owned(), price))
+}
+
+/// Prints the age of every price, prices not fetched are as old as the
+/// portfolio files.
+fn warn_price_ages(portfolio: &Portfolio, updated_at: &HashMap<String, u64>, args: &Args) {
+    let now = SystemTime::now();
+    let file_age = args
+        .file
+        .iter()
+        .filter_map(|path| fs::metadata(path).ok()?.modified().ok())
+        .min()
+        .and_then(|modified| now.duration_since(modified).ok());
+    for stock in portfolio.Stocks.iter() {
+        let age = match updated_at.get(&stock.WKN) {
+            Some(updated) => now
+                .duration_since(UNIX_EPOCH + Duration::from_secs(*updated))
+                .ok(),
+            None => file_age,
+        };
+        match age {
+            Some(age) => log::warn!(
+                "Price {} of {} is {} old",
+                stock.Price,
+                stock.WKN,
+                format_age(age)
+            ),
+            None => log::warn!("Price {} of {} has unknown age", stock.Price, stock.WKN),
+        }
+    }
+}
+
+fn format_age(age: Duration) -> String {
+    match age.as_secs() {
+        secs if secs < 3600 => format!("{} min", secs / 60),
+        secs if secs < 2 * 86400 => format!("{} h", secs / 3600),
+        secs => format!("{} days", secs / 86400),
+    }
+}
+
+/// Fills in Yahoo Finance symbols with OpenFIGI. The other providers look up
+/// stocks without a symbol by ISIN themselves.
+fn resolve_symbols(portfolio: &mut Portfolio, args: &Args) {
+    if !matches!(args.provider, Provider::Yahoo) {
+        return;
+    }
+    if let Err(err) = figi::resolve_symbols(portfolio) {
+        log::warn!("Resolving symbols with OpenFIGI failed: {err}");
+    }
+}
+
+fn cached(provider: Box<dyn PriceProvider>, args: &Args) -> Result<CachedProvider, Error> {
+    CachedProvider::new(provider, Duration::from_secs(args.price_cache_ttl))
+}
+
+fn load_portfolio(path: &str, args: &Args) -> Result<Portfolio, Error> {
+    let mut content = Vec::new();
+    match path {
+        "-" => io::stdin().lock().read_to_end(&mut content)?,
+        path => File::open(path)?.read_to_end(&mut content)?,
+    };
+    let format = args.input_format.unwrap_or_else(|| {
+        let format = InputFormat::detect(path, &content);
+        log::info!("Detected input format {format:?}");
+        format
+    });
+    let portfolio_file = content.as_slice();
+
+    Ok(match format {
+        InputFormat::Json => serde_json::from_reader(portfolio_file)?,
+        InputFormat::Csv => {
+            Portfolio::from_csv_reader_with_dialect(portfolio_file, &csv_dialect(args)?)?
+        }
+        InputFormat::Dkb => import::read_dkb(portfolio_file, &load_goal_ratios(args)?)?,
+        InputFormat::Comdirect => import::read_comdirect(portfolio_file, &load_goal_ratios(args)?)?,
+        InputFormat::IbkrFlex => import::read_ibkr_flex(portfolio_file, &load_goal_ratios(args)?)?,
+        InputFormat::Degiro => import::read_degiro(portfolio_file, &load_goal_ratios(args)?)?,
+        InputFormat::Fidelity => load_us_broker(portfolio_file, UsBroker::Fidelity, args)?,
+        InputFormat::Schwab => load_us_broker(portfolio_file, UsBroker::Schwab, args)?,
+        InputFormat::Vanguard => load_us_broker(portfolio_file, UsBroker::Vanguard, args)?,
+        InputFormat::Ledger => import::read_ledger(portfolio_file, &load_goal_ratios(args)?)?,
+        InputFormat::Ghostfolio => {
+            import::read_ghostfolio(portfolio_file, &load_goal_ratios(args)?)?
+        }
+    })
+}
+
+fn csv_dialect(args: &Args) -> Result<CsvDialect, Error> {
+    if !args.csv_delimiter.is_ascii() {
+        simple_error::bail!("CSV delimiter has to be an ASCII character");
+    }
+    Ok(CsvDialect {
+        delimiter: args.csv_delimiter as u8,
+        decimal_comma: args.decimal_comma,
+    })
+}
+
+fn load_us_broker(file: impl Read, broker: UsBroker, args: &Args) -> Result<Portfolio, Error> {
+    import::read_us_broker(file, broker, &load_goal_ratios(args)?)
+}
+
+/// Accounts of the portfolio files, named by their `Account` or path, and
+/// accounts only holding cash
+fn load_accounts(portfolios: &[Portfolio], args: &Args) -> Result<Vec<Account>, Error> {
+    let mut accounts: Vec<Account> = Vec::new();
+    for (path, portfolio) in args.file.iter().zip(portfolios) {
+        let name = portfolio.Account.as_deref().unwrap_or(path);
+        match accounts.iter_mut().find(|a| a.name == name) {
+            Some(account) => {
+                for stock in portfolio.Stocks.iter() {
+                    *account.holdings.entry(stock.WKN.clone()).or_default() += stock.Shares;
+                }
+            }
+            None => accounts.push(Account::new(name, portfolio)),
+        }
+    }
+    for (name, cash) in args.account_cash.iter() {
+        match accounts.iter_mut().find(|a| &a.name == name) {
+            Some(account) => account.cash += cash,
+            None => accounts.push(Account {
+                name: name.clone(),
+                cash: *cash,
+                ..Default::default()
+            }),
+        }
+    }
+    Ok(accounts)
+}
+
+fn load_goal_ratios(args: &Args) -> Result<GoalRatios, Error> {
+    if let Some(path) = &args.targets {
+        return targets::flatten_targets(&targets::read_targets(File::open(path)?)?);
+    }
+    let path = args.goal_ratios.as_ref().ok_or::<Error>(
+        simple_error::simple_error!("--goal-ratios or --targets is required for broker exports")
+            .into(),
+    )?;
+    import::read_goal_ratios(File::open(path)?)
+}
@@ -0,0 +1,72 @@
+/// Whole cents of `amount`, rounded half away from zero.
+///
+/// Cents are summed as integers or as integral `f64`, which is exact up to
+/// 2^53 cents, so budget checks are not affected by floating point errors.
+pub fn to_cents(amount: f64) -> i64 {
+    (amount * 100.0).round() as i64
+}
+
+pub fn from_cents(cents: i64) -> f64 {
+    cents as f64 / 100.0
+}
+
+/// `amount` rounded to whole cents
+pub fn round_cents(amount: f64) -> f64 {
+    from_cents(to_cents(amount))
+}
+
+/// Decimal places of the minor unit of the ISO 4217 `currency`
+pub fn currency_decimals(currency: &str) -> u32 {
+    match currency {
+        "BIF" | "CLP" | "DJF" | "GNF" | "ISK" | "JPY" | "KMF" | "KRW" | "PYG" | "RWF" | "UGX"
+        | "VND" | "VUV" | "XAF" | "XOF" | "XPF" => 0,
+        "BHD" | "IQD" | "JOD" | "KWD" | "LYD" | "OMR" | "TND" => 3,
+        _ => 2,
+    }
+}
+
+/// `amount` rounded half away from zero to the minor unit of `currency`
+pub fn round_to_currency(amount: f64, currency: &str) -> f64 {
+    let factor = 10_f64.powi(currency_decimals(currency) as i32);
+    (amount * factor).round() / factor
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_cents_rounds_half_away_from_zero() {
+        assert_eq!(to_cents(0.125), 13);
+        assert_eq!(to_cents(-0.125), -13);
+        assert_eq!(to_cents(-0.004), 0);
+        assert_eq!(to_cents(2.5), 250);
+    }
+
+    #[test]
+    fn to_cents_absorbs_float_noise() {
+        assert_eq!(to_cents(0.1 + 0.2), 30);
+        assert_eq!(to_cents(3.0 * 33.33), 9999);
+        assert_eq!(to_cents(110.0 / 1.1), 10000);
+    }
+
+    #[test]
+    fn round_cents_round_trips() {
+        assert_eq!(round_cents(99.99999999999999), 100.0);
+        assert_eq!(round_cents(-12.344), -12.34);
+        assert_eq!(from_cents(to_cents(19.99)), 19.99);
+    }
+
+    #[test]
+    fn round_to_currency_uses_the_minor_unit() {
+        assert_eq!(round_to_currency(109.999999, "USD"), 110.0);
+        assert_eq!(round_to_currency(15012.6, "JPY"), 15013.0);
+        assert_eq!(round_to_currency(1.23456, "KWD"), 1.235);
+    }
+
+    #[test]
+    fn cent_sums_are_exact() {
+        let total: i64 = std::iter::repeat_n(0.1, 1000).map(to_cents).sum();
+        assert_eq!(from_cents(total), 100.0);
+    }
+}
@@ -0,0 +1,427 @@
+use crate::accounts::Placement;
+use crate::fees::FeeModel;
+use crate::harvest::Harvest;
+use crate::money::{currency_decimals, round_cents, round_to_currency};
+use crate::{Error, Portfolio};
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct PlanEntry {
+    pub WKN: String,
+    pub ISIN: String,
+    pub Symbol: String,
+    pub Price: f64,
+    pub Shares: f64,
+    pub NewShares: f64,
+    pub OrderValue: f64,
+    pub Fee: f64,
+    /// Cost of trading at the effective price, see [`crate::Stock::slippage`]
+    pub Slippage: f64,
+    /// Estimated tax on the realized gain of a sale, negative for a loss
+    /// offsetting other gains
+    pub Tax: f64,
+    pub GoalRatio: f64,
+    pub CurrentRatio: f64,
+    pub ActualRatio: f64,
+    /// Reduction of the absolute deviation from the goal ratio
+    pub DriftImprovement: f64,
+    /// Currency the stock is traded in if not the base currency
+    pub Currency: String,
+    /// Price in `Currency`, see [`Plan::apply_native_prices`]
+    pub NativePrice: Option<f64>,
+    /// Order value in `Currency`, see [`Plan::apply_native_prices`]
+    pub NativeOrderValue: Option<f64>,
+}
+
+impl PlanEntry {
+    /// Currency the stock is traded in, `base` if it is the base currency
+    fn currency_or<'a>(&'a self, base: &'a str) -> &'a str {
+        match self.Currency.is_empty() {
+            true => base,
+            false => &self.Currency,
+        }
+    }
+}
+
+/// Aggregate figures of a plan
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct PlanSummary {
+    pub Invested: f64,
+    pub Fees: f64,
+    pub Slippage: f64,
+    pub Taxes: f64,
+    pub LeftoverCash: f64,
+    /// Sum of the absolute order values
+    pub Turnover: f64,
+    pub BuyOrders: usize,
+    pub SellOrders: usize,
+    /// Sum of the absolute deviations from the goal ratios after the plan
+    pub RemainingDrift: f64,
+}
+
+/// Row of a generic basket order file as accepted by broker order imports
+#[derive(Serialize)]
+struct Order<'a> {
+    symbol: &'a str,
+    side: &'static str,
+    quantity: f64,
+    limit: f64,
+    currency: &'a str,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum SortOrder {
+    /// Alphabetically by WKN
+    Wkn,
+    /// Largest remaining deviation from the goal ratio first
+    Drift,
+    /// Largest order value first
+    OrderSize,
+}
+
+/// Row of a Portfolio Performance portfolio transaction CSV import
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PortfolioPerformanceTransaction<'a> {
+    date: &'a str,
+    #[serde(rename = "Type")]
+    transaction_type: &'static str,
+    #[serde(rename = "ISIN")]
+    isin: &'a str,
+    #[serde(rename = "WKN")]
+    wkn: &'a str,
+    #[serde(rename = "Ticker Symbol")]
+    symbol: &'a str,
+    shares: f64,
+    value: String,
+    #[serde(rename = "Transaction Currency")]
+    currency: &'a str,
+}
+
+/// Expected dividend inflow with the shares after the plan
+#[allow(non_snake_case)]
+#[derive(Debug, Default, Serialize)]
+pub struct DividendForecast {
+    /// Dividends per share of the last 12 months times the shares after the
+    /// plan, in the base currency
+    pub Annual: f64,
+    /// Announced dividends not paid yet
+    pub Upcoming: Vec<UpcomingDividend>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct UpcomingDividend {
+    pub WKN: String,
+    pub ExDate: String,
+    pub PaymentDate: Option<String>,
+    /// Dividend for all shares after the plan, in the base currency
+    pub Amount: f64,
+}
+
+/// Proceeds of a sale paying for (part of) a purchase
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct Transfer {
+    /// WKN of the stock sold
+    pub From: String,
+    /// WKN of the stock bought
+    pub To: String,
+    pub Amount: f64,
+}
+
+/// Result of a rebalancing with per-stock orders and resulting ratios.
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct Plan {
+    pub Stocks: Vec<PlanEntry>,
+    pub Invested: f64,
+    /// Fees of all orders, paid from the reinvest amount
+    pub Fees: f64,
+    /// Slippage of all orders, paid from the reinvest amount
+    pub Slippage: f64,
+    /// Estimated taxes of all sales, paid from their proceeds
+    pub Taxes: f64,
+    pub LeftoverCash: f64,
+    /// Part of the leftover cash kept by the cash goal ratio
+    pub CashGoal: f64,
+    /// Free cash of the portfolio before the plan, see [`Plan::new_cash`]
+    pub Cash: f64,
+    pub CurrentValue: f64,
+    pub NewValue: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub Dividends: Option<DividendForecast>,
+    /// Sales paired with the purchases they pay for, see [`Plan::pair_orders`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub Transfers: Vec<Transfer>,
+    /// Suggested sales realizing losses, see [`crate::harvest::harvest_losses`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub Harvests: Vec<Harvest>,
+    /// Orders split across accounts, see [`crate::accounts::place_orders`]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub Placements: Vec<Placement>,
+}
+
+impl Plan {
+    pub fn new(
+        portfolio: &Portfolio,
+        new_amounts_map: &HashMap<String, f64>,
+        optimal_reinvest: f64,
+        reinvest_amount: f64,
+    ) -> Self {
+        let current_sum = portfolio
+            .Stocks
+            .iter()
+            .fold(0.0, |acc, elem| acc + elem.Price * elem.Shares);
+        let actual_sum = portfolio.Stocks.iter().fold(0.0, |acc, elem| {
+            acc + elem.Price * (elem.Shares + new_amounts_map.get(&elem.WKN).unwrap_or(&0.0))
+        });
+
+        let stocks = portfolio
+            .Stocks
+            .iter()
+            .map(|stock| {
+                let new_amount = *new_amounts_map.get(&stock.WKN).unwrap_or(&0.0);
+                let current_ratio = (stock.Price * stock.Shares) / current_sum;
+                let actual_ratio = (stock.Price * (stock.Shares + new_amount)) / actual_sum;
+                PlanEntry {
+                    WKN: stock.WKN.clone(),
+                    ISIN: stock.ISIN.clone(),
+                    Symbol: stock.Symbol.clone(),
+                    Price: stock.Price,
+                    Shares: stock.Shares,
+                    NewShares: new_amount,
+                    OrderValue: round_cents(stock.Price * new_amount),
+                    Fee: 0.0,
+                    Slippage: 0.0,
+                    Tax: 0.0,
+                    GoalRatio: stock.GoalRatio,
+                    CurrentRatio: current_ratio,
+                    ActualRatio: actual_ratio,
+                    DriftImprovement: (current_ratio - stock.GoalRatio).abs()
+                        - (actual_ratio - stock.GoalRatio).abs(),
+                    Currency: String::new(),
+                    NativePrice: None,
+                    NativeOrderValue: None,
+                }
+            })
+            .collect();
+
+        Plan {
+            Stocks: stocks,
+            Invested: optimal_reinvest,
+            Fees: 0.0,
+            Slippage: 0.0,
+            Taxes: 0.0,
+            LeftoverCash: round_cents(reinvest_amount - optimal_reinvest),
+            CashGoal: round_cents(portfolio.cash_goal(reinvest_amount)),
+            Cash: round_cents(portfolio.free_cash()),
+            CurrentValue: round_cents(current_sum),
+            NewValue: round_cents(actual_sum),
+            Dividends: None,
+            Transfers: Vec::new(),
+            Harvests: Vec::new(),
+            Placements: Vec::new(),
+        }
+    }
+
+    /// Adds the prices and order values in the currency of the stocks of
+    /// `native_portfolio` which are not traded in `base`, before their prices
+    /// were converted.
+    pub fn apply_native_prices(&mut self, native_portfolio: &Portfolio, base: &str) {
+        for entry in self.Stocks.iter_mut() {
+            if let Some(stock) = native_portfolio
+                .Stocks
+                .iter()
+                .find(|s| s.WKN == entry.WKN && !s.Currency.is_empty() && s.Currency != base)
+            {
+                entry.Currency = stock.Currency.clone();
+                entry.NativePrice = Some(stock.Price);
+                entry.NativeOrderValue = Some(round_cents(stock.Price * entry.NewShares));
+            }
+        }
+    }
+
+    /// Free cash after paying for the orders with their fees, slippage and
+    /// taxes
+    pub fn new_cash(&self) -> f64 {
+        round_cents(self.Cash - self.Invested - self.Fees - self.Slippage - self.Taxes)
+    }
+
+    /// Charges the fees of every order, reducing the leftover cash.
+    pub fn apply_fees(&mut self, fees: &FeeModel) {
+        for entry in self.Stocks.iter_mut() {
+            entry.Fee = round_cents(fees.fee(entry.OrderValue));
+        }
+        self.Fees = round_cents(self.Stocks.iter().map(|e| e.Fee).sum());
+        self.LeftoverCash = round_cents(self.LeftoverCash - self.Fees);
+    }
+
+    /// Pairs the proceeds of the sales with the purchases they pay for, the
+    /// largest orders first.
+    pub fn pair_orders(&mut self) {
+        let orders = |sell: bool| {
+            self.Stocks
+                .iter()
+                .filter(|e| (e.OrderValue < 0.0) == sell && e.OrderValue != 0.0)
+                .map(|e| (e.WKN.clone(), e.OrderValue.abs()))
+                .sorted_by(|a, b| b.1.total_cmp(&a.1))
+                .collect::<Vec<_>>()
+        };
+        let (mut sells, mut buys) = (orders(true), orders(false));
+
+        let mut transfers = Vec::new();
+        let (mut sell_idx, mut buy_idx) = (0, 0);
+        while sell_idx < sells.len() && buy_idx < buys.len() {
+            let amount = sells[sell_idx].1.min(buys[buy_idx].1);
+            transfers.push(Transfer {
+                From: sells[sell_idx].0.clone(),
+                To: buys[buy_idx].0.clone(),
+                Amount: amount,
+            });
+            sells[sell_idx].1 -= amount;
+            buys[buy_idx].1 -= amount;
+            // Rounding noise below a cent does not pay for anything
+            if sells[sell_idx].1 < 0.005 {
+                sell_idx += 1;
+            }
+            if buys[buy_idx].1 < 0.005 {
+                buy_idx += 1;
+            }
+        }
+        self.Transfers = transfers;
+    }
+
+    /// Charges the slippage of every order at the `Slippage` of its stock,
+    /// reducing the leftover cash.
+    pub fn apply_slippage(&mut self, portfolio: &Portfolio) {
+        for entry in self.Stocks.iter_mut() {
+            if let Some(stock) = portfolio.Stocks.iter().find(|s| s.WKN == entry.WKN) {
+                entry.Slippage = round_cents(stock.slippage(entry.OrderValue));
+            }
+        }
+        self.Slippage = round_cents(self.Stocks.iter().map(|e| e.Slippage).sum());
+        self.LeftoverCash = round_cents(self.LeftoverCash - self.Slippage);
+    }
+
+    /// Charges the tax on the realized gains of every sale at `tax_rate`,
+    /// reducing the leftover cash.
+    pub fn apply_taxes(&mut self, portfolio: &Portfolio, tax_rate: f64) {
+        for entry in self.Stocks.iter_mut().filter(|e| e.NewShares < 0.0) {
+            if let Some(stock) = portfolio.Stocks.iter().find(|s| s.WKN == entry.WKN) {
+                entry.Tax = round_cents(tax_rate * stock.realized_gain(-entry.NewShares));
+            }
+        }
+        self.Taxes = round_cents(self.Stocks.iter().map(|e| e.Tax).sum());
+        self.LeftoverCash = round_cents(self.LeftoverCash - self.Taxes);
+    }
+
+    pub fn summary(&self) -> PlanSummary {
+        PlanSummary {
+            Invested: self.Invested,
+            Fees: self.Fees,
+            Slippage: self.Slippage,
+            Taxes: self.Taxes,
+            LeftoverCash: self.LeftoverCash,
+            Turnover: round_cents(self.Stocks.iter().map(|e| e.OrderValue.abs()).sum()),
+            BuyOrders: self.Stocks.iter().filter(|e| e.NewShares > 0.0).count(),
+            SellOrders: self.Stocks.iter().filter(|e| e.NewShares < 0.0).count(),
+            RemainingDrift: self
+                .Stocks
+                .iter()
+                .map(|e| (e.ActualRatio - e.GoalRatio).abs())
+                .sum(),
+        }
+    }
+
+    pub fn sort(&mut self, order: SortOrder) {
+        match order {
+            SortOrder::Wkn => self.Stocks.sort_by(|a, b| a.WKN.cmp(&b.WKN)),
+            SortOrder::Drift => self.Stocks.sort_by(|a, b| {
+                let drift = |e: &PlanEntry| (e.ActualRatio - e.GoalRatio).abs();
+                drift(b).total_cmp(&drift(a))
+            }),
+            SortOrder::OrderSize => self
+                .Stocks
+                .sort_by(|a, b| b.OrderValue.abs().total_cmp(&a.OrderValue.abs())),
+        }
+    }
+
+    /// Writes one row per stock with its order.
+    pub fn to_csv_writer<W: Write>(&self, writer: W) -> Result<(), Error> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        for entry in self.Stocks.iter() {
+            csv_writer.serialize(entry)?;
+        }
+        csv_writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the orders of the plan as `symbol,side,quantity,limit,currency`
+    /// CSV with the current price in the currency the stock is traded in as
+    /// limit, falling back to `base`.
+    pub fn to_order_csv_writer<W: Write>(&self, writer: W, base: &str) -> Result<(), Error> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        for entry in self.Stocks.iter().filter(|e| e.NewShares != 0.0) {
+            let currency = entry.currency_or(base);
+            let symbol = [&entry.Symbol, &entry.ISIN, &entry.WKN]
+                .into_iter()
+                .find(|id| !id.is_empty())
+                .unwrap_or(&entry.WKN);
+            csv_writer.serialize(Order {
+                symbol,
+                side: match entry.NewShares > 0.0 {
+                    true => "BUY",
+                    false => "SELL",
+                },
+                quantity: entry.NewShares.abs(),
+                limit: round_to_currency(entry.NativePrice.unwrap_or(entry.Price), currency),
+                currency,
+            })?;
+        }
+        csv_writer.flush()?;
+        Ok(())
+    }
+
+    /// Writes the orders of the plan as Portfolio Performance transaction CSV,
+    /// assuming they are executed at the current price on `date`. Values are
+    /// in the currency the stock is traded in, falling back to `base`.
+    pub fn to_portfolio_performance_writer<W: Write>(
+        &self,
+        writer: W,
+        date: &str,
+        base: &str,
+    ) -> Result<(), Error> {
+        let mut csv_writer = csv::WriterBuilder::new()
+            .delimiter(b';')
+            .from_writer(writer);
+        for entry in self.Stocks.iter().filter(|e| e.NewShares != 0.0) {
+            let currency = entry.currency_or(base);
+            let value = entry.NativeOrderValue.unwrap_or(entry.OrderValue);
+            csv_writer.serialize(PortfolioPerformanceTransaction {
+                date,
+                transaction_type: match entry.NewShares > 0.0 {
+                    true => "Buy",
+                    false => "Sell",
+                },
+                isin: &entry.ISIN,
+                wkn: &entry.WKN,
+                symbol: &entry.Symbol,
+                shares: entry.NewShares.abs(),
+                value: format!(
+                    "{:.*}",
+                    currency_decimals(currency) as usize,
+                    round_to_currency(value.abs(), currency)
+                ),
+                currency,
+            })?;
+        }
+        csv_writer.flush()?;
+        Ok(())
+    }
+}
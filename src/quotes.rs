@@ -0,0 +1,924 @@
+use crate::fx::{self, FxRates};
+use crate::plan::{DividendForecast, Plan, UpcomingDividend};
+use crate::{credentials, date, Error, Portfolio};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const YAHOO_CHART_URL: &str = "https://query1.finance.yahoo.com/v8/finance/chart";
+const ALPHA_VANTAGE_URL: &str = "https://www.alphavantage.co/query";
+const FINNHUB_URL: &str = "https://finnhub.io/api/v1";
+const COINGECKO_PRICE_URL: &str = "https://api.coingecko.com/api/v3/simple/price";
+
+/// Number of requests in flight at once in `PriceProvider::fetch_batch`
+pub const MAX_CONCURRENT_REQUESTS: usize = 8;
+
+/// Attempts of a request failing with a connection error, HTTP status 429 or
+/// a server error
+const MAX_ATTEMPTS: u32 = 4;
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Minimal time between requests to hosts with rate limits on free plans
+const MIN_REQUEST_INTERVALS: &[(&str, Duration)] = &[
+    ("alphavantage.co", Duration::from_millis(1200)),
+    ("finnhub.io", Duration::from_millis(1000)),
+    ("coingecko.com", Duration::from_millis(2000)),
+    ("openfigi.com", Duration::from_millis(2500)),
+];
+
+/// Query parameters holding API keys, their values are left out of logs
+const SECRET_PARAMETERS: &[&str] = &["apikey", "token"];
+
+static NEXT_REQUESTS: Mutex<BTreeMap<&str, Instant>> = Mutex::new(BTreeMap::new());
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Refuses all network access, providers then fail and cached quotes are used
+/// regardless of their age.
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Price of a security as reported by a provider
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Quote {
+    pub price: f64,
+    /// ISO code of the currency of `price`, if reported by the provider
+    pub currency: Option<String>,
+    /// Unix timestamp in seconds of the last update of `price`, if reported
+    pub updated: Option<u64>,
+}
+
+/// Daily open, high, low and close price
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Bar {
+    /// YYYY-MM-DD
+    pub Date: String,
+    pub Open: f64,
+    pub High: f64,
+    pub Low: f64,
+    pub Close: f64,
+}
+
+/// Dividend per share
+#[allow(non_snake_case)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Dividend {
+    /// YYYY-MM-DD
+    pub ExDate: String,
+    /// YYYY-MM-DD, if reported by the provider
+    pub PaymentDate: Option<String>,
+    pub Amount: f64,
+}
+
+/// Row of the historical prices CSV
+#[allow(non_snake_case)]
+#[derive(Serialize)]
+struct HistoryRow<'a> {
+    WKN: &'a str,
+    Symbol: &'a str,
+    Date: &'a str,
+    Open: f64,
+    High: f64,
+    Low: f64,
+    Close: f64,
+}
+
+/// Source of current quotes.
+///
+/// Implement this to use another data source with `update_prices`.
+pub trait PriceProvider: Sync {
+    /// Name for log and error messages
+    fn name(&self) -> &str;
+
+    /// Quote of a ticker symbol.
+    fn fetch(&self, symbol: &str) -> Result<Quote, Error>;
+
+    /// Daily bars of a ticker symbol from `from` to `to` inclusive, both
+    /// YYYY-MM-DD. Not supported unless overridden.
+    fn fetch_history(&self, symbol: &str, _from: &str, _to: &str) -> Result<Vec<Bar>, Error> {
+        simple_error::bail!(
+            "{} does not provide historical prices for '{}'",
+            self.name(),
+            symbol
+        )
+    }
+
+    /// Paid and announced dividends of a ticker symbol with ex-date from
+    /// `from` on, YYYY-MM-DD. Not supported unless overridden.
+    fn fetch_dividends(&self, symbol: &str, _from: &str) -> Result<Vec<Dividend>, Error> {
+        simple_error::bail!(
+            "{} does not provide dividends for '{}'",
+            self.name(),
+            symbol
+        )
+    }
+
+    /// Market capitalization of a ticker symbol in the currency of its quote.
+    /// Not supported unless overridden.
+    fn fetch_market_cap(&self, symbol: &str) -> Result<f64, Error> {
+        simple_error::bail!(
+            "{} does not provide market caps for '{}'",
+            self.name(),
+            symbol
+        )
+    }
+
+    /// Quote of an ISIN, `None` if the provider cannot look up ISINs.
+    fn fetch_by_isin(&self, _isin: &str) -> Result<Option<Quote>, Error> {
+        Ok(None)
+    }
+
+    /// Quotes of several ticker symbols, symbols without quote are missing in
+    /// the result. Fetches up to `MAX_CONCURRENT_REQUESTS` symbols at once
+    /// and skips symbols failing to fetch unless overridden.
+    fn fetch_batch(&self, symbols: &[&str]) -> Result<HashMap<String, Quote>, Error> {
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(Vec::new());
+        thread::scope(|scope| {
+            for _ in 0..MAX_CONCURRENT_REQUESTS.min(symbols.len()) {
+                scope.spawn(|| {
+                    while let Some(symbol) = symbols.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        // Errors are not Send, so only their message leaves the thread
+                        let quote = self.fetch(symbol).map_err(|err| err.to_string());
+                        results.lock().unwrap().push((symbol.to_string(), quote));
+                    }
+                });
+            }
+        });
+
+        Ok(results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .filter_map(|(symbol, quote)| match quote {
+                Ok(quote) => Some((symbol, quote)),
+                Err(err) => {
+                    log::warn!("Failed to fetch {} quote of {symbol}: {err}", self.name());
+                    None
+                }
+            })
+            .collect())
+    }
+}
+
+/// Built-in providers of current quotes
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default)]
+pub enum Provider {
+    /// Unofficial Yahoo Finance chart endpoint, no API key needed
+    #[default]
+    Yahoo,
+    /// Alpha Vantage global quote, needs an API key
+    AlphaVantage,
+    /// Finnhub quote, needs an API key, looks up symbols by ISIN
+    Finnhub,
+}
+
+impl Provider {
+    pub fn build(&self) -> Result<Box<dyn PriceProvider>, Error> {
+        Ok(match self {
+            Provider::Yahoo => Box::new(YahooFinance),
+            Provider::AlphaVantage => Box::new(AlphaVantage {
+                api_key: credentials::ALPHA_VANTAGE.require()?,
+            }),
+            Provider::Finnhub => Box::new(Finnhub {
+                api_key: credentials::FINNHUB.require()?,
+            }),
+        })
+    }
+}
+
+/// Fetches `url` with curl.
+pub(crate) fn fetch_text(url: &str) -> Result<String, Error> {
+    curl(url, &[], &[])
+}
+
+/// Posts `body` as JSON to `url` with curl and parses the response as JSON.
+pub(crate) fn post_json(url: &str, body: &Value, headers: &[String]) -> Result<Value, Error> {
+    let body = body.to_string();
+    let args = [
+        "--header",
+        "Content-Type: application/json",
+        "--data",
+        &body,
+    ];
+    Ok(serde_json::from_str(&curl(url, &args, headers)?)?)
+}
+
+/// Waits until the minimal interval since the last request to the host of
+/// `url` has passed.
+fn wait_for_rate_limit(url: &str) {
+    let Some((host, interval)) = MIN_REQUEST_INTERVALS
+        .iter()
+        .find(|(host, _)| url.contains(host))
+    else {
+        return;
+    };
+    // Reserve the next slot while locked, so concurrent requests queue up
+    let slot = {
+        let mut next_requests = NEXT_REQUESTS.lock().unwrap();
+        let next = next_requests.entry(host).or_insert_with(Instant::now);
+        let slot = (*next).max(Instant::now());
+        *next = slot + *interval;
+        slot
+    };
+    thread::sleep(slot.saturating_duration_since(Instant::now()));
+}
+
+/// Replaces the values of the secret query parameters of `url` for logging.
+fn redact(url: &str) -> String {
+    let Some((base, query)) = url.split_once('?') else {
+        return url.to_owned();
+    };
+    let query = query
+        .split('&')
+        .map(|parameter| match parameter.split_once('=') {
+            Some((name, _)) if SECRET_PARAMETERS.contains(&name.to_lowercase().as_str()) => {
+                format!("{name}=***")
+            }
+            _ => parameter.to_owned(),
+        })
+        .join("&");
+    format!("{base}?{query}")
+}
+
+/// Quotes `value` for a curl config file.
+fn config_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Runs curl on `url` with `args` and the secret `headers`.
+///
+/// The URL and headers hold API keys, so they are passed in a config file on
+/// stdin rather than on the command line, where other users could see them.
+fn curl(url: &str, args: &[&str], headers: &[String]) -> Result<String, Error> {
+    if is_offline() {
+        simple_error::bail!("Network access to {} refused in offline mode", redact(url));
+    }
+
+    let mut config = format!("url = {}\n", config_string(url));
+    for header in headers {
+        config += &format!("header = {}\n", config_string(header));
+    }
+
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        wait_for_rate_limit(url);
+        log::debug!("Fetching {}", redact(url));
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--location"])
+            .args(["--write-out", "\n%{http_code}"])
+            .args(args)
+            .args(["--config", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| simple_error::simple_error!("Failed to run curl: {}", err))?;
+        child.stdin.take().unwrap().write_all(config.as_bytes())?;
+        let output = child.wait_with_output()?;
+        let stdout = String::from_utf8(output.stdout)?;
+        let (body, status) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+
+        // Connection errors have status 000
+        let message = match status.parse::<u16>().unwrap_or(0) {
+            200..=299 if output.status.success() => return Ok(body.to_owned()),
+            0 => String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+            status @ (429 | 500..=599) => format!("HTTP status {status}"),
+            status => simple_error::bail!(
+                "Request to {} failed with HTTP status {}",
+                redact(url),
+                status
+            ),
+        };
+        if attempt == MAX_ATTEMPTS {
+            simple_error::bail!("Request to {} failed: {}", redact(url), message);
+        }
+        log::warn!(
+            "Request to {} failed: {message}, retrying in {backoff:?}",
+            redact(url)
+        );
+        thread::sleep(backoff);
+        backoff *= 2;
+    }
+    unreachable!("the last attempt returns")
+}
+
+/// Fetches `url` with curl and parses the response as JSON.
+fn fetch_json(url: &str) -> Result<Value, Error> {
+    Ok(serde_json::from_str(&fetch_text(url)?)?)
+}
+
+/// Unofficial Yahoo Finance chart endpoint
+pub struct YahooFinance;
+
+impl PriceProvider for YahooFinance {
+    fn name(&self) -> &str {
+        "Yahoo Finance"
+    }
+
+    fn fetch(&self, symbol: &str) -> Result<Quote, Error> {
+        let response = fetch_json(&format!("{YAHOO_CHART_URL}/{symbol}?interval=1d&range=1d"))?;
+        let meta = &response["chart"]["result"][0]["meta"];
+        let Some(price) = meta["regularMarketPrice"].as_f64() else {
+            simple_error::bail!("No Yahoo Finance quote for '{}'", symbol);
+        };
+        // London listings are quoted in pence
+        let (price, currency) = match meta["currency"].as_str() {
+            Some("GBp") => (price / 100.0, Some("GBP".to_owned())),
+            currency => (price, currency.map(str::to_owned)),
+        };
+        Ok(Quote {
+            price,
+            currency,
+            updated: meta["regularMarketTime"].as_u64(),
+        })
+    }
+
+    fn fetch_history(&self, symbol: &str, from: &str, to: &str) -> Result<Vec<Bar>, Error> {
+        let response = fetch_json(&format!(
+            "{YAHOO_CHART_URL}/{symbol}?interval=1d&period1={}&period2={}",
+            date::to_timestamp(from)?,
+            date::to_timestamp(to)? + 86400
+        ))?;
+        let result = &response["chart"]["result"][0];
+        let Some(timestamps) = result["timestamp"].as_array() else {
+            simple_error::bail!("No Yahoo Finance history for '{}'", symbol);
+        };
+        let prices = &result["indicators"]["quote"][0];
+        let price = |field: &str, idx: usize| prices[field][idx].as_f64();
+
+        // Days without trading have null prices
+        Ok(timestamps
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, timestamp)| {
+                Some(Bar {
+                    Date: date::from_timestamp(timestamp.as_i64()?),
+                    Open: price("open", idx)?,
+                    High: price("high", idx)?,
+                    Low: price("low", idx)?,
+                    Close: price("close", idx)?,
+                })
+            })
+            .collect())
+    }
+
+    fn fetch_dividends(&self, symbol: &str, from: &str) -> Result<Vec<Dividend>, Error> {
+        let response = fetch_json(&format!(
+            "{YAHOO_CHART_URL}/{symbol}?interval=1d&period1={}&period2={}&events=div",
+            date::to_timestamp(from)?,
+            date::to_timestamp(&date::today())? + 86400
+        ))?;
+        let events = &response["chart"]["result"][0]["events"]["dividends"];
+        let mut dividends: Vec<Dividend> = events
+            .as_object()
+            .into_iter()
+            .flat_map(|dividends| dividends.values())
+            .filter_map(|dividend| {
+                Some(Dividend {
+                    ExDate: date::from_timestamp(dividend["date"].as_i64()?),
+                    PaymentDate: None,
+                    Amount: dividend["amount"].as_f64()?,
+                })
+            })
+            .collect();
+        dividends.sort_by(|a, b| a.ExDate.cmp(&b.ExDate));
+        Ok(dividends)
+    }
+}
+
+/// Alpha Vantage global quote endpoint
+pub struct AlphaVantage {
+    pub api_key: String,
+}
+
+impl PriceProvider for AlphaVantage {
+    fn name(&self) -> &str {
+        "Alpha Vantage"
+    }
+
+    fn fetch(&self, symbol: &str) -> Result<Quote, Error> {
+        let response = fetch_json(&format!(
+            "{ALPHA_VANTAGE_URL}?function=GLOBAL_QUOTE&symbol={symbol}&apikey={}",
+            self.api_key
+        ))?;
+        // Rate limit and key errors are reported with status 200 and a message
+        if let Some(message) = response["Note"]
+            .as_str()
+            .or(response["Information"].as_str())
+            .or(response["Error Message"].as_str())
+        {
+            simple_error::bail!("Alpha Vantage: {}", message);
+        }
+        let Some(price) = response["Global Quote"]["05. price"]
+            .as_str()
+            .and_then(|price| price.parse().ok())
+        else {
+            simple_error::bail!("No Alpha Vantage quote for '{}'", symbol);
+        };
+        Ok(Quote {
+            price,
+            currency: None,
+            updated: None,
+        })
+    }
+
+    fn fetch_history(&self, symbol: &str, from: &str, to: &str) -> Result<Vec<Bar>, Error> {
+        let response = fetch_json(&format!(
+            "{ALPHA_VANTAGE_URL}?function=TIME_SERIES_DAILY&symbol={symbol}&outputsize=full&apikey={}",
+            self.api_key
+        ))?;
+        let Some(series) = response["Time Series (Daily)"].as_object() else {
+            simple_error::bail!("No Alpha Vantage history for '{}'", symbol);
+        };
+        let price = |bar: &Value, field: &str| bar[field].as_str()?.parse().ok();
+
+        // Dates are YYYY-MM-DD and thus compare like strings
+        let mut bars: Vec<Bar> = series
+            .iter()
+            .filter(|(day, _)| from <= day.as_str() && day.as_str() <= to)
+            .filter_map(|(day, bar)| {
+                Some(Bar {
+                    Date: day.clone(),
+                    Open: price(bar, "1. open")?,
+                    High: price(bar, "2. high")?,
+                    Low: price(bar, "3. low")?,
+                    Close: price(bar, "4. close")?,
+                })
+            })
+            .collect();
+        bars.sort_by(|a, b| a.Date.cmp(&b.Date));
+        Ok(bars)
+    }
+
+    fn fetch_market_cap(&self, symbol: &str) -> Result<f64, Error> {
+        let response = fetch_json(&format!(
+            "{ALPHA_VANTAGE_URL}?function=OVERVIEW&symbol={symbol}&apikey={}",
+            self.api_key
+        ))?;
+        match response["MarketCapitalization"]
+            .as_str()
+            .and_then(|cap| cap.parse().ok())
+        {
+            Some(cap) => Ok(cap),
+            None => simple_error::bail!("No Alpha Vantage market cap for '{}'", symbol),
+        }
+    }
+
+    fn fetch_dividends(&self, symbol: &str, from: &str) -> Result<Vec<Dividend>, Error> {
+        let response = fetch_json(&format!(
+            "{ALPHA_VANTAGE_URL}?function=DIVIDENDS&symbol={symbol}&apikey={}",
+            self.api_key
+        ))?;
+        let Some(data) = response["data"].as_array() else {
+            simple_error::bail!("No Alpha Vantage dividends for '{}'", symbol);
+        };
+        // Dates of announced dividends not yet known are "None"
+        let day = |value: &Value| value.as_str().filter(|d| *d != "None").map(str::to_owned);
+
+        let mut dividends: Vec<Dividend> = data
+            .iter()
+            .filter_map(|dividend| {
+                Some(Dividend {
+                    ExDate: day(&dividend["ex_dividend_date"])?,
+                    PaymentDate: day(&dividend["payment_date"]),
+                    Amount: dividend["amount"].as_str()?.parse().ok()?,
+                })
+            })
+            .filter(|dividend| dividend.ExDate.as_str() >= from)
+            .collect();
+        dividends.sort_by(|a, b| a.ExDate.cmp(&b.ExDate));
+        Ok(dividends)
+    }
+}
+
+/// Finnhub quote and symbol search endpoints
+pub struct Finnhub {
+    pub api_key: String,
+}
+
+impl Finnhub {
+    /// Symbol of the first Finnhub search result for `isin`.
+    pub fn lookup_symbol(&self, isin: &str) -> Result<Option<String>, Error> {
+        let response = fetch_json(&format!(
+            "{FINNHUB_URL}/search?q={isin}&token={}",
+            self.api_key
+        ))?;
+        let symbol = response["result"][0]["symbol"].as_str().map(str::to_owned);
+        if let Some(symbol) = &symbol {
+            log::info!("Resolved ISIN {isin} to symbol {symbol}");
+        }
+        Ok(symbol)
+    }
+}
+
+impl PriceProvider for Finnhub {
+    fn name(&self) -> &str {
+        "Finnhub"
+    }
+
+    fn fetch(&self, symbol: &str) -> Result<Quote, Error> {
+        let response = fetch_json(&format!(
+            "{FINNHUB_URL}/quote?symbol={symbol}&token={}",
+            self.api_key
+        ))?;
+        // Unknown symbols are answered with a current price of 0
+        match response["c"].as_f64() {
+            Some(price) if price > 0.0 => Ok(Quote {
+                price,
+                currency: None,
+                updated: response["t"].as_u64(),
+            }),
+            _ => simple_error::bail!("No Finnhub quote for '{}'", symbol),
+        }
+    }
+
+    fn fetch_market_cap(&self, symbol: &str) -> Result<f64, Error> {
+        let response = fetch_json(&format!(
+            "{FINNHUB_URL}/stock/profile2?symbol={symbol}&token={}",
+            self.api_key
+        ))?;
+        // Reported in millions
+        match response["marketCapitalization"].as_f64() {
+            Some(cap) if cap > 0.0 => Ok(cap * 1e6),
+            _ => simple_error::bail!("No Finnhub market cap for '{}'", symbol),
+        }
+    }
+
+    fn fetch_by_isin(&self, isin: &str) -> Result<Option<Quote>, Error> {
+        match self.lookup_symbol(isin)? {
+            Some(symbol) => Ok(Some(self.fetch(&symbol)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// CoinGecko simple price endpoint, symbols are coin ids like `bitcoin`
+pub struct CoinGecko {
+    /// ISO code of the currency to quote in
+    pub currency: String,
+}
+
+impl PriceProvider for CoinGecko {
+    fn name(&self) -> &str {
+        "CoinGecko"
+    }
+
+    fn fetch(&self, symbol: &str) -> Result<Quote, Error> {
+        self.fetch_batch(&[symbol])?.remove(symbol).ok_or_else(|| {
+            simple_error::simple_error!("No CoinGecko price for '{}'", symbol).into()
+        })
+    }
+
+    fn fetch_batch(&self, symbols: &[&str]) -> Result<HashMap<String, Quote>, Error> {
+        let currency = self.currency.to_lowercase();
+        let response = match fetch_json(&format!(
+            "{COINGECKO_PRICE_URL}?ids={}&vs_currencies={currency}&include_last_updated_at=true",
+            symbols.join(",")
+        )) {
+            Ok(response) => response,
+            Err(err) => {
+                for id in symbols {
+                    log::warn!("Failed to fetch {} quote of {id}: {err}", self.name());
+                }
+                return Ok(HashMap::new());
+            }
+        };
+        Ok(symbols
+            .iter()
+            .filter_map(|id| {
+                let quote = Quote {
+                    price: response[id][&currency].as_f64()?,
+                    currency: Some(self.currency.clone()),
+                    updated: response[id]["last_updated_at"].as_u64(),
+                };
+                Some((id.to_string(), quote))
+            })
+            .collect())
+    }
+}
+
+/// Coin ids are lowercase like `bitcoin` or `wrapped-bitcoin`, in contrast to
+/// ticker symbols.
+fn is_coin_id(symbol: &str) -> bool {
+    !symbol.is_empty()
+        && symbol
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Quotes by WKN of all stocks with a `Symbol`, or an ISIN the provider can
+/// look up.
+///
+/// Stocks with a coin id like `bitcoin` as `Symbol` are quoted by `coins`
+/// first, e.g. `CoinGecko`.
+pub fn fetch_quotes(
+    portfolio: &Portfolio,
+    provider: &dyn PriceProvider,
+    coins: &dyn PriceProvider,
+) -> Result<HashMap<String, Quote>, Error> {
+    let (coin_ids, symbols): (Vec<&str>, Vec<&str>) = portfolio
+        .Stocks
+        .iter()
+        .map(|s| s.Symbol.as_str())
+        .filter(|symbol| !symbol.is_empty())
+        .unique()
+        .partition(|symbol| is_coin_id(symbol));
+
+    let mut quotes = match coin_ids.is_empty() {
+        true => HashMap::new(),
+        false => coins.fetch_batch(&coin_ids)?,
+    };
+    let symbols: Vec<&str> = symbols
+        .into_iter()
+        .chain(coin_ids.into_iter().filter(|id| !quotes.contains_key(*id)))
+        .collect();
+    if !symbols.is_empty() {
+        quotes.extend(provider.fetch_batch(&symbols)?);
+    }
+
+    let mut by_wkn = HashMap::new();
+    for stock in portfolio.Stocks.iter() {
+        let quote = match quotes.get(&stock.Symbol).cloned() {
+            Some(quote) => Some(quote),
+            None if stock.Symbol.is_empty() && !stock.ISIN.is_empty() => {
+                provider.fetch_by_isin(&stock.ISIN).unwrap_or_else(|err| {
+                    log::warn!("Failed to fetch quote of {}: {err}", stock.ISIN);
+                    None
+                })
+            }
+            None => None,
+        };
+        match quote {
+            Some(quote) => {
+                by_wkn.insert(stock.WKN.clone(), quote);
+            }
+            None => log::warn!(
+                "No {} quote for {}, keeping price {}",
+                provider.name(),
+                stock.WKN,
+                stock.Price
+            ),
+        }
+    }
+    Ok(by_wkn)
+}
+
+/// Overrides price and currency of the stocks by their quote in `quotes` by
+/// WKN.
+pub fn apply_quotes(portfolio: &mut Portfolio, quotes: &HashMap<String, Quote>) {
+    for stock in portfolio.Stocks.iter_mut() {
+        let Some(quote) = quotes.get(&stock.WKN) else {
+            continue;
+        };
+        log::info!(
+            "Updated price of {} from {} to {}{}",
+            stock.WKN,
+            stock.Price,
+            quote.price,
+            quote
+                .currency
+                .as_ref()
+                .map_or(String::new(), |c| format!(" {c}"))
+        );
+        stock.Price = quote.price;
+        if let Some(currency) = &quote.currency {
+            stock.Currency = currency.clone();
+        }
+    }
+}
+
+/// Stocks with their price in the portfolio and in `quotes` by WKN, whose
+/// relative difference exceeds `threshold`. These likely are symbol or
+/// currency mixups.
+pub fn suspicious_moves(
+    portfolio: &Portfolio,
+    quotes: &HashMap<String, Quote>,
+    threshold: f64,
+) -> Vec<(String, f64, f64)> {
+    portfolio
+        .Stocks
+        .iter()
+        .filter_map(|stock| {
+            let quote = quotes.get(&stock.WKN)?;
+            let moved = stock.Price > 0.0 && (quote.price / stock.Price - 1.0).abs() > threshold;
+            moved.then(|| (stock.WKN.clone(), stock.Price, quote.price))
+        })
+        .collect()
+}
+
+/// Overrides the prices of all stocks by current quotes, see `fetch_quotes`.
+///
+/// Returns the applied quotes by WKN.
+pub fn update_prices(
+    portfolio: &mut Portfolio,
+    provider: &dyn PriceProvider,
+    coins: &dyn PriceProvider,
+) -> Result<HashMap<String, Quote>, Error> {
+    let quotes = fetch_quotes(portfolio, provider, coins)?;
+    apply_quotes(portfolio, &quotes);
+    Ok(quotes)
+}
+
+/// Writes the daily bars from `from` to `to` of all stocks with a `Symbol` as
+/// CSV.
+pub fn write_history<W: Write>(
+    writer: W,
+    portfolio: &Portfolio,
+    provider: &dyn PriceProvider,
+    from: &str,
+    to: &str,
+) -> Result<(), Error> {
+    let mut csv_writer = csv::Writer::from_writer(writer);
+    for stock in portfolio.Stocks.iter() {
+        if stock.Symbol.is_empty() {
+            log::warn!("No symbol for {}, skipping its history", stock.WKN);
+            continue;
+        }
+        for bar in provider.fetch_history(&stock.Symbol, from, to)? {
+            csv_writer.serialize(HistoryRow {
+                WKN: &stock.WKN,
+                Symbol: &stock.Symbol,
+                Date: &bar.Date,
+                Open: bar.Open,
+                High: bar.High,
+                Low: bar.Low,
+                Close: bar.Close,
+            })?;
+        }
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
+
+/// Sets the goal ratios proportional to the market caps of the stocks. With
+/// `top`, only the largest `top` stocks are kept as the universe and the others
+/// get a goal ratio of 0 to be sold off.
+pub fn apply_market_cap_weights(
+    portfolio: &mut Portfolio,
+    provider: &dyn PriceProvider,
+    top: Option<usize>,
+) -> Result<(), Error> {
+    let mut caps = Vec::new();
+    for stock in portfolio.Stocks.iter() {
+        if stock.Symbol.is_empty() {
+            simple_error::bail!("No symbol for {} to fetch its market cap", stock.WKN);
+        }
+        caps.push(provider.fetch_market_cap(&stock.Symbol)?);
+    }
+    if let Some(top) = top {
+        if let Some(&smallest) = caps
+            .iter()
+            .sorted_by(|a, b| b.total_cmp(a))
+            .nth(top.saturating_sub(1))
+        {
+            for cap in caps.iter_mut().filter(|cap| **cap < smallest) {
+                *cap = 0.0;
+            }
+        }
+    }
+    let total: f64 = caps.iter().sum();
+    if total == 0.0 {
+        simple_error::bail!("No market caps to weight by");
+    }
+    for (stock, cap) in portfolio.Stocks.iter_mut().zip(caps) {
+        log::info!(
+            "Goal ratio of {} {:.4} -> {:.4}",
+            stock.WKN,
+            stock.GoalRatio,
+            cap / total
+        );
+        stock.GoalRatio = cap / total;
+    }
+    Ok(())
+}
+
+/// Expected dividends of the holdings after `plan` from the dividends of the
+/// last 12 months and announced ones, converted from the currency of each
+/// stock into `base` at `rates`. Stocks without dividend data or exchange rate
+/// are skipped.
+pub fn forecast_dividends(
+    plan: &Plan,
+    provider: &dyn PriceProvider,
+    base: &str,
+    rates: &FxRates,
+) -> DividendForecast {
+    let today = date::today();
+    let year_ago = date::to_timestamp(&today)
+        .map_or(today.clone(), |now| date::from_timestamp(now - 365 * 86400));
+
+    let mut forecast = DividendForecast::default();
+    for entry in plan.Stocks.iter().filter(|e| !e.Symbol.is_empty()) {
+        let dividends = match provider.fetch_dividends(&entry.Symbol, &year_ago) {
+            Ok(dividends) => dividends,
+            Err(err) => {
+                log::warn!("No dividends for {}: {err}", entry.WKN);
+                continue;
+            }
+        };
+        // Dividends are paid in the currency the stock is traded in
+        let rate = match fx::convert(1.0, &entry.Currency, base, rates) {
+            Ok(rate) => rate,
+            Err(err) => {
+                log::warn!("No dividends for {}: {err}", entry.WKN);
+                continue;
+            }
+        };
+        let shares = entry.Shares + entry.NewShares;
+        for dividend in dividends {
+            let paid_date = dividend.PaymentDate.as_ref().unwrap_or(&dividend.ExDate);
+            if year_ago <= dividend.ExDate && dividend.ExDate <= today {
+                forecast.Annual += dividend.Amount * shares * rate;
+            }
+            if *paid_date >= today {
+                forecast.Upcoming.push(UpcomingDividend {
+                    WKN: entry.WKN.clone(),
+                    ExDate: dividend.ExDate,
+                    PaymentDate: dividend.PaymentDate,
+                    Amount: dividend.Amount * shares * rate,
+                });
+            }
+        }
+    }
+    forecast
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_hides_api_keys() {
+        assert_eq!(
+            redact("https://finnhub.io/api/v1/quote?symbol=AAPL&token=SECRET"),
+            "https://finnhub.io/api/v1/quote?symbol=AAPL&token=***"
+        );
+        assert_eq!(
+            redact("https://www.alphavantage.co/query?apikey=SECRET&symbol=IBM"),
+            "https://www.alphavantage.co/query?apikey=***&symbol=IBM"
+        );
+        assert_eq!(redact(YAHOO_CHART_URL), YAHOO_CHART_URL);
+    }
+
+    /// Pays a dividend of 1 per share a month ago for every symbol
+    struct MonthlyDividend;
+
+    impl PriceProvider for MonthlyDividend {
+        fn name(&self) -> &str {
+            "monthly dividend"
+        }
+
+        fn fetch(&self, symbol: &str) -> Result<Quote, Error> {
+            simple_error::bail!("No quote for {}", symbol)
+        }
+
+        fn fetch_dividends(&self, _symbol: &str, _from: &str) -> Result<Vec<Dividend>, Error> {
+            let now = date::to_timestamp(&date::today()).unwrap();
+            Ok(vec![Dividend {
+                ExDate: date::from_timestamp(now - 30 * 86400),
+                PaymentDate: None,
+                Amount: 1.0,
+            }])
+        }
+    }
+
+    #[test]
+    fn dividends_are_converted_to_base_currency() {
+        let stock = |symbol: &str, currency: &str| crate::Stock {
+            WKN: symbol.to_owned(),
+            Symbol: symbol.to_owned(),
+            Price: 10.0,
+            Shares: 10.0,
+            GoalRatio: 1.0,
+            Currency: currency.to_owned(),
+            ..Default::default()
+        };
+        let native = Portfolio {
+            Stocks: vec![stock("EU", ""), stock("US", "USD"), stock("UK", "GBP")],
+            ..Default::default()
+        };
+        let mut plan = Plan::new(&native, &HashMap::new(), 0.0, 0.0);
+        plan.apply_native_prices(&native, "EUR");
+        let rates = FxRates::from([
+            ("EUR".to_owned(), 1.0),
+            ("USD".to_owned(), 1.25),
+            ("GBP".to_owned(), 0.8),
+        ]);
+
+        let forecast = forecast_dividends(&plan, &MonthlyDividend, "EUR", &rates);
+        assert!((forecast.Annual - (10.0 + 8.0 + 12.5)).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::locale::Locale;
+use crate::plan::Plan;
+use crate::Error;
+use std::f64::consts::PI;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+const COLORS: &[&str] = &[
+    "#4e79a7", "#f28e2b", "#e15759", "#76b7b2", "#59a14f", "#edc948", "#b07aa1", "#ff9da7",
+    "#9c755f", "#bab0ac",
+];
+
+/// Writes a self-contained HTML page with the plan, an allocation pie chart
+/// and drift bars.
+pub fn write_html_report<W: Write>(
+    mut writer: W,
+    plan: &Plan,
+    locale: &Locale,
+) -> Result<(), Error> {
+    writeln!(
+        writer,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Rebalancing plan</title>\n\
+         <style>body {{ font-family: sans-serif; }} td, th {{ padding: 2px 8px; text-align: right; }}</style>\n\
+         </head>\n<body>\n<h1>Rebalancing plan</h1>"
+    )?;
+
+    writeln!(
+        writer,
+        "<table>\n<tr><th>WKN</th><th>Price</th><th>Shares</th><th>New Shares</th>\
+         <th>Order Value</th><th>Goal Ratio</th><th>Actual Ratio</th></tr>"
+    )?;
+    for entry in plan.Stocks.iter() {
+        writeln!(
+            writer,
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+            escape(&entry.WKN),
+            escape(&locale.money(entry.Price)),
+            locale.shares(entry.Shares),
+            locale.shares(entry.NewShares),
+            escape(&locale.money(entry.OrderValue)),
+            locale.number(entry.GoalRatio, 4),
+            locale.number(entry.ActualRatio, 4)
+        )?;
+    }
+    writeln!(
+        writer,
+        "</table>\n<p>Would reinvest {}, leftover cash {}</p>",
+        escape(&locale.money(plan.Invested)),
+        escape(&locale.money(plan.LeftoverCash))
+    )?;
+
+    writeln!(writer, "<h2>Allocation</h2>")?;
+    write_pie_chart(&mut writer, plan)?;
+    writeln!(writer, "<h2>Drift from goal ratio</h2>")?;
+    write_drift_bars(&mut writer, plan)?;
+
+    writeln!(writer, "</body>\n</html>")?;
+    Ok(())
+}
+
+fn write_pie_chart<W: Write>(writer: &mut W, plan: &Plan) -> Result<(), Error> {
+    let (radius, center) = (100.0, 110.0);
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"400\" height=\"220\">"
+    )?;
+
+    let mut angle: f64 = 0.0;
+    for (idx, entry) in plan.Stocks.iter().enumerate() {
+        let color = COLORS[idx % COLORS.len()];
+        let sweep = entry.ActualRatio * 2.0 * PI;
+        let point = |a: f64| (center + radius * a.sin(), center - radius * a.cos());
+        let (x0, y0) = point(angle);
+        let (x1, y1) = point(angle + sweep);
+        let large_arc = u8::from(sweep > PI);
+
+        if entry.ActualRatio >= 1.0 {
+            writeln!(
+                writer,
+                "<circle cx=\"{center}\" cy=\"{center}\" r=\"{radius}\" fill=\"{color}\"/>"
+            )?;
+        } else {
+            writeln!(
+                writer,
+                "<path d=\"M{center},{center} L{x0:.2},{y0:.2} A{radius},{radius} 0 {large_arc} 1 {x1:.2},{y1:.2} Z\" fill=\"{color}\"/>"
+            )?;
+        }
+        writeln!(
+            writer,
+            "<rect x=\"240\" y=\"{}\" width=\"12\" height=\"12\" fill=\"{color}\"/>\
+             <text x=\"258\" y=\"{}\" font-size=\"12\">{} {:.1}%</text>",
+            10 + idx * 18,
+            21 + idx * 18,
+            escape(&entry.WKN),
+            entry.ActualRatio * 100.0
+        )?;
+        angle += sweep;
+    }
+
+    writeln!(writer, "</svg>")?;
+    Ok(())
+}
+
+fn write_drift_bars<W: Write>(writer: &mut W, plan: &Plan) -> Result<(), Error> {
+    let (center, scale) = (250.0, 1000.0);
+    let height = 20 + plan.Stocks.len() * 22;
+    writeln!(
+        writer,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"500\" height=\"{height}\">"
+    )?;
+    writeln!(
+        writer,
+        "<line x1=\"{center}\" y1=\"0\" x2=\"{center}\" y2=\"{height}\" stroke=\"black\"/>"
+    )?;
+
+    for (idx, entry) in plan.Stocks.iter().enumerate() {
+        let drift = entry.ActualRatio - entry.GoalRatio;
+        // Clamp to the drawing area, 1 percentage point are 10 pixels
+        let width = (drift.abs() * scale).min(center - 80.0);
+        let x = match drift < 0.0 {
+            true => center - width,
+            false => center,
+        };
+        let y = 10 + idx * 22;
+        let color = match drift < 0.0 {
+            true => "#e15759",
+            false => "#59a14f",
+        };
+        writeln!(
+            writer,
+            "<text x=\"5\" y=\"{}\" font-size=\"12\">{}</text>\
+             <rect x=\"{x:.2}\" y=\"{y}\" width=\"{width:.2}\" height=\"16\" fill=\"{color}\"/>\
+             <text x=\"430\" y=\"{}\" font-size=\"12\">{:+.2}%</text>",
+            y + 12,
+            escape(&entry.WKN),
+            y + 12,
+            drift * 100.0
+        )?;
+    }
+
+    writeln!(writer, "</svg>")?;
+    Ok(())
+}
+
+/// Writes the allocation pie chart and drift bars as `allocation.svg` and
+/// `drift.svg` into `directory`.
+pub fn write_svg_charts(directory: &Path, plan: &Plan) -> Result<(), Error> {
+    fs::create_dir_all(directory)?;
+    write_pie_chart(&mut File::create(directory.join("allocation.svg"))?, plan)?;
+    write_drift_bars(&mut File::create(directory.join("drift.svg"))?, plan)?;
+    Ok(())
+}
+
+/// Writes a single-page PDF with the plan and allocation bars of goal and
+/// actual ratio per stock.
+pub fn write_pdf_report<W: Write>(
+    mut writer: W,
+    plan: &Plan,
+    locale: &Locale,
+) -> Result<(), Error> {
+    let (row_height, bar_x, bar_width) = (14.0, 400.0, 150.0);
+    let max_ratio = plan
+        .Stocks
+        .iter()
+        .flat_map(|e| [e.GoalRatio, e.ActualRatio])
+        .fold(0.0, f64::max);
+
+    let mut content = String::new();
+    let mut text = |x: f64, y: f64, size: u8, value: &str| {
+        content.push_str(&format!(
+            "BT /F1 {size} Tf {x:.1} {y:.1} Td ({}) Tj ET\n",
+            pdf_escape(value)
+        ));
+    };
+
+    let mut y = 800.0;
+    text(40.0, y, 16, "Rebalancing plan");
+    y -= 2.0 * row_height;
+    let columns = [40.0, 110.0, 160.0, 210.0, 290.0, 345.0];
+    for (x, title) in columns
+        .iter()
+        .zip(["WKN", "Shares", "New", "Order Value", "Goal", "Actual"])
+    {
+        text(*x, y, 10, title);
+    }
+    text(bar_x, y, 10, "Allocation goal / actual");
+
+    let mut bars = String::new();
+    for entry in plan.Stocks.iter() {
+        y -= row_height;
+        let values = [
+            entry.WKN.clone(),
+            locale.shares(entry.Shares),
+            locale.shares(entry.NewShares),
+            locale.money(entry.OrderValue),
+            locale.number(entry.GoalRatio, 4),
+            locale.number(entry.ActualRatio, 4),
+        ];
+        for (x, value) in columns.iter().zip(values.iter()) {
+            text(*x, y, 10, value);
+        }
+        let goal_width = entry.GoalRatio / max_ratio * bar_width;
+        let actual_width = entry.ActualRatio / max_ratio * bar_width;
+        bars.push_str(&format!(
+            "0.7 g {bar_x:.1} {:.1} {goal_width:.1} 4 re f\n0.31 0.47 0.65 rg {bar_x:.1} {:.1} {actual_width:.1} 4 re f\n",
+            y + 5.0,
+            y,
+        ));
+    }
+
+    y -= 2.0 * row_height;
+    let summary = plan.summary();
+    for line in [
+        format!("Would reinvest {}", locale.money(summary.Invested)),
+        format!("Fees {}", locale.money(summary.Fees)),
+        format!("Slippage {}", locale.money(summary.Slippage)),
+        format!("Taxes {}", locale.money(summary.Taxes)),
+        format!("Leftover cash {}", locale.money(summary.LeftoverCash)),
+        format!(
+            "Portfolio value {} -> {}",
+            locale.money(plan.CurrentValue),
+            locale.money(plan.NewValue)
+        ),
+        format!(
+            "Orders {} buy, {} sell",
+            summary.BuyOrders, summary.SellOrders
+        ),
+    ] {
+        text(40.0, y, 10, &line);
+        y -= row_height;
+    }
+    content.push_str(&bars);
+
+    let objects = [
+        "<< /Type /Catalog /Pages 2 0 R >>".to_owned(),
+        "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_owned(),
+        "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 595 842] /Contents 4 0 R \
+         /Resources << /Font << /F1 5 0 R >> >> >>"
+            .to_owned(),
+        format!(
+            "<< /Length {} >>\nstream\n{content}endstream",
+            content.len()
+        ),
+        "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica /Encoding /WinAnsiEncoding >>"
+            .to_owned(),
+    ];
+
+    let mut pdf = String::from("%PDF-1.4\n");
+    let mut offsets = Vec::new();
+    for (idx, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.push_str(&format!("{} 0 obj\n{object}\nendobj\n", idx + 1));
+    }
+    let xref_offset = pdf.len();
+    pdf.push_str(&format!(
+        "xref\n0 {}\n0000000000 65535 f \n",
+        objects.len() + 1
+    ));
+    for offset in offsets {
+        pdf.push_str(&format!("{offset:010} 00000 n \n"));
+    }
+    pdf.push_str(&format!(
+        "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{xref_offset}\n%%EOF\n",
+        objects.len() + 1
+    ));
+
+    writer.write_all(pdf.as_bytes())?;
+    Ok(())
+}
+
+/// Escapes text for a PDF string in WinAnsi encoding.
+fn pdf_escape(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '(' | ')' | '\\' => format!("\\{c}"),
+            '€' => "\\200".to_owned(),
+            '£' => "\\243".to_owned(),
+            c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+            c if c.is_whitespace() => " ".to_owned(),
+            _ => "?".to_owned(),
+        })
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,187 @@
+use crate::quotes::PriceProvider;
+use crate::{Error, Portfolio};
+use itertools::Itertools;
+use std::collections::{BTreeSet, HashMap};
+
+/// Iterations of the risk parity solver, which converges within a few dozen
+const MAX_ITERATIONS: usize = 1000;
+
+/// How to weight the stocks by the risk of their daily returns
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum WeightMethod {
+    /// Weights inversely proportional to the volatility, ignoring correlations
+    InverseVolatility,
+    /// Weights with equal contributions to the portfolio variance
+    #[default]
+    RiskParity,
+}
+
+/// Computes goal ratios from the daily closes between `from` and `to` and sets
+/// them in `portfolio`. Only days with a close of every stock count, so every
+/// stock needs a symbol with a history.
+pub fn apply_risk_weights(
+    portfolio: &mut Portfolio,
+    provider: &dyn PriceProvider,
+    from: &str,
+    to: &str,
+    method: WeightMethod,
+) -> Result<(), Error> {
+    let mut closes = Vec::new();
+    for stock in portfolio.Stocks.iter() {
+        if stock.Symbol.is_empty() {
+            simple_error::bail!("No symbol for {} to weight it by its history", stock.WKN);
+        }
+        let bars = provider.fetch_history(&stock.Symbol, from, to)?;
+        closes.push(
+            bars.into_iter()
+                .map(|bar| (bar.Date, bar.Close))
+                .collect::<HashMap<_, _>>(),
+        );
+    }
+    let dates: BTreeSet<&String> = closes
+        .iter()
+        .map(|c| c.keys().collect::<BTreeSet<_>>())
+        .reduce(|a, b| a.intersection(&b).copied().collect())
+        .unwrap_or_default();
+    if dates.len() < 3 {
+        simple_error::bail!("Too few common days between {} and {}", from, to);
+    }
+
+    let returns = closes
+        .iter()
+        .map(|c| {
+            dates
+                .iter()
+                .tuple_windows()
+                .map(|(a, b)| (c[*b] / c[*a]).ln())
+                .collect_vec()
+        })
+        .collect_vec();
+    let covariance = covariance(&returns);
+    if covariance.iter().enumerate().any(|(i, row)| row[i] <= 0.0) {
+        simple_error::bail!("Prices without variance between {} and {}", from, to);
+    }
+    let weights = match method {
+        WeightMethod::InverseVolatility => inverse_volatility(&covariance),
+        WeightMethod::RiskParity => risk_parity(&covariance),
+    };
+    for (stock, weight) in portfolio.Stocks.iter_mut().zip(weights) {
+        log::info!(
+            "Goal ratio of {} {:.4} -> {:.4}",
+            stock.WKN,
+            stock.GoalRatio,
+            weight
+        );
+        stock.GoalRatio = weight;
+    }
+    Ok(())
+}
+
+/// Sample covariance matrix of the returns of each stock
+fn covariance(returns: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let days = returns.first().map_or(0, Vec::len) as f64;
+    let means = returns
+        .iter()
+        .map(|r| r.iter().sum::<f64>() / days)
+        .collect_vec();
+    (0..returns.len())
+        .map(|i| {
+            (0..returns.len())
+                .map(|j| {
+                    returns[i]
+                        .iter()
+                        .zip(returns[j].iter())
+                        .map(|(a, b)| (a - means[i]) * (b - means[j]))
+                        .sum::<f64>()
+                        / (days - 1.0)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn inverse_volatility(covariance: &[Vec<f64>]) -> Vec<f64> {
+    let inverse = (0..covariance.len())
+        .map(|i| 1.0 / covariance[i][i].sqrt())
+        .collect_vec();
+    let sum: f64 = inverse.iter().sum();
+    inverse.into_iter().map(|w| w / sum).collect()
+}
+
+/// Equal risk contributions by cyclical coordinate descent, solving
+/// `w_i * (Σw)_i = 1/n` for each weight in turn and normalizing at the end
+fn risk_parity(covariance: &[Vec<f64>]) -> Vec<f64> {
+    let n = covariance.len();
+    let budget = 1.0 / n as f64;
+    let mut weights = inverse_volatility(covariance);
+    for _ in 0..MAX_ITERATIONS {
+        let mut change: f64 = 0.0;
+        for i in 0..n {
+            let variance = covariance[i][i];
+            let others: f64 = (0..n)
+                .filter(|&j| j != i)
+                .map(|j| covariance[i][j] * weights[j])
+                .sum();
+            let weight =
+                (-others + (others * others + 4.0 * variance * budget).sqrt()) / (2.0 * variance);
+            change = change.max((weight - weights[i]).abs() / weight);
+            weights[i] = weight;
+        }
+        if change < 1e-10 {
+            break;
+        }
+    }
+    let sum: f64 = weights.iter().sum();
+    weights.into_iter().map(|w| w / sum).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-9, "{actual:?} is not {expected:?}");
+        }
+    }
+
+    #[test]
+    fn covariance_of_proportional_returns() {
+        let covariance = covariance(&[vec![1.0, 2.0, 3.0], vec![2.0, 4.0, 6.0]]);
+        assert_close(&covariance[0], &[1.0, 2.0]);
+        assert_close(&covariance[1], &[2.0, 4.0]);
+    }
+
+    #[test]
+    fn inverse_volatility_ignores_correlations() {
+        let weights = inverse_volatility(&[vec![1.0, 0.5], vec![0.5, 4.0]]);
+        assert_close(&weights, &[2.0 / 3.0, 1.0 / 3.0]);
+    }
+
+    #[test]
+    fn risk_parity_equalizes_risk_contributions() {
+        let covariance = [vec![0.04, 0.006], vec![0.006, 0.09]];
+        let weights = risk_parity(&covariance);
+        let contributions = (0..2)
+            .map(|i| weights[i] * (0..2).map(|j| covariance[i][j] * weights[j]).sum::<f64>())
+            .collect_vec();
+        assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        assert!(
+            (contributions[0] - contributions[1]).abs() < 1e-9,
+            "{contributions:?}"
+        );
+        // The less volatile stock gets the larger weight
+        assert!(weights[0] > weights[1], "{weights:?}");
+    }
+
+    #[test]
+    fn risk_parity_of_uncorrelated_stocks_is_inverse_volatility() {
+        let covariance = [
+            vec![0.01, 0.0, 0.0],
+            vec![0.0, 0.04, 0.0],
+            vec![0.0, 0.0, 0.16],
+        ];
+        assert_close(&risk_parity(&covariance), &inverse_volatility(&covariance));
+    }
+}
@@ -0,0 +1,126 @@
+use crate::locale::Locale;
+use crate::{get_fractional_reinvest_amounts, Portfolio, ReinvestOptions};
+use itertools::Itertools;
+use prettytable::{format, row, Table};
+use serde::Serialize;
+
+/// Monthly amount of the savings plan of a stock
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct SavingsRate {
+    pub WKN: String,
+    pub Amount: f64,
+}
+
+/// Distributes the recurring `amount` across savings plans in whole units of
+/// the currency, moving the portfolio towards the goal ratios. Stocks whose
+/// rate would fall below the broker's `minimum` are left out one at a time,
+/// the smallest rate first.
+pub fn savings_plan(
+    portfolio: &Portfolio,
+    amount: f64,
+    minimum: f64,
+    options: &ReinvestOptions,
+) -> Vec<SavingsRate> {
+    // Savings plans only buy
+    let options = ReinvestOptions {
+        no_selling: true,
+        ..*options
+    };
+    let mut portfolio = portfolio.clone();
+    let rates = loop {
+        let (stocks, new_amounts) = get_fractional_reinvest_amounts(&portfolio, amount, &options);
+        let rates = stocks
+            .iter()
+            .zip(new_amounts.iter())
+            .map(|(stock, new_amount)| (stock.WKN.clone(), new_amount * stock.Price))
+            .collect_vec();
+        match rates
+            .iter()
+            .filter(|(_, rate)| *rate < minimum)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+        {
+            Some((wkn, rate)) => {
+                log::debug!("Savings rate {rate:.2} of {wkn} is below the minimum");
+                portfolio.Stocks.retain(|s| &s.WKN != wkn);
+            }
+            None => break rates,
+        }
+    };
+
+    // Largest remainder apportionment of the whole units
+    let mut amounts = rates.iter().map(|(_, rate)| rate.floor()).collect_vec();
+    let left = (amount.floor() - amounts.iter().sum::<f64>()).max(0.0) as usize;
+    for idx in (0..rates.len())
+        .sorted_by(|&a, &b| (rates[b].1 - amounts[b]).total_cmp(&(rates[a].1 - amounts[a])))
+        .take(left)
+    {
+        amounts[idx] += 1.0;
+    }
+
+    rates
+        .into_iter()
+        .zip(amounts)
+        .map(|((wkn, _), amount)| SavingsRate {
+            WKN: wkn,
+            Amount: amount,
+        })
+        .collect()
+}
+
+pub fn print_savings_plan(rates: &[SavingsRate], locale: &Locale) {
+    let total: f64 = rates.iter().map(|r| r.Amount).sum();
+    let mut table = Table::new();
+    table.set_titles(row!["WKN", "Amount", "Share"]);
+    for rate in rates {
+        table.add_row(row![
+            rate.WKN,
+            locale.money(rate.Amount),
+            locale.number(rate.Amount / total, 4)
+        ]);
+    }
+    table.set_format(*format::consts::FORMAT_NO_BORDER);
+
+    println!("\n{table}");
+    println!("Savings plans {}\n", locale.money(total));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Stock;
+
+    fn stock(wkn: &str, goal_ratio: f64) -> Stock {
+        Stock {
+            WKN: wkn.to_owned(),
+            Price: 10.0,
+            GoalRatio: goal_ratio,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rates_below_the_minimum_are_dropped() {
+        let portfolio = Portfolio {
+            Stocks: vec![stock("A", 0.45), stock("B", 0.45), stock("C", 0.1)],
+            ..Default::default()
+        };
+        let rates = savings_plan(&portfolio, 100.0, 25.0, &ReinvestOptions::default());
+        let rates = rates
+            .iter()
+            .map(|r| (r.WKN.as_str(), r.Amount))
+            .collect_vec();
+        assert_eq!(rates, [("A", 50.0), ("B", 50.0)]);
+    }
+
+    #[test]
+    fn rates_are_whole_units_of_the_amount() {
+        let portfolio = Portfolio {
+            Stocks: vec![stock("A", 1.0), stock("B", 1.0), stock("C", 1.0)],
+            ..Default::default()
+        };
+        let rates = savings_plan(&portfolio, 100.0, 0.0, &ReinvestOptions::default());
+        assert!(rates.iter().all(|r| r.Amount.fract() == 0.0), "{rates:?}");
+        assert_eq!(rates.iter().map(|r| r.Amount).sum::<f64>(), 100.0);
+    }
+}
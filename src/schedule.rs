@@ -0,0 +1,115 @@
+use crate::locale::Locale;
+use crate::plan::Plan;
+use crate::{calculate_optimal_reinvest, Error, Portfolio, ReinvestOptions};
+use prettytable::{format, Cell, Row, Table};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Orders of one month of a contribution schedule
+#[allow(non_snake_case)]
+#[derive(Debug, Serialize)]
+pub struct MonthPlan {
+    pub Month: usize,
+    /// New shares by WKN, stocks without an order are left out
+    pub Orders: BTreeMap<String, f64>,
+    pub Invested: f64,
+    /// Cash carried over to the next month
+    pub LeftoverCash: f64,
+    /// Sum of the absolute deviations from the goal ratios after the month
+    pub RemainingDrift: f64,
+}
+
+/// Plans the orders of `months` monthly contributions at the current prices,
+/// each month investing the contribution and the cash left over from the
+/// month before.
+pub fn plan_months(
+    portfolio: &Portfolio,
+    contribution: f64,
+    months: usize,
+    options: &ReinvestOptions,
+) -> Result<Vec<MonthPlan>, Error> {
+    let mut portfolio = portfolio.clone();
+    let mut cash = 0.0;
+    let mut schedule = Vec::with_capacity(months);
+    for month in 1..=months {
+        let reinvest = cash + contribution;
+        let (invested, new_amounts_map, _) =
+            calculate_optimal_reinvest(&portfolio, reinvest, options)?;
+        let mut plan = Plan::new(&portfolio, &new_amounts_map, invested, reinvest);
+        plan.apply_fees(&options.fees);
+        plan.apply_slippage(&portfolio);
+        plan.apply_taxes(&portfolio, options.tax_rate);
+        cash = plan.LeftoverCash;
+
+        schedule.push(MonthPlan {
+            Month: month,
+            Orders: plan
+                .Stocks
+                .iter()
+                .filter(|e| e.NewShares != 0.0)
+                .map(|e| (e.WKN.clone(), e.NewShares))
+                .collect(),
+            Invested: invested,
+            LeftoverCash: cash,
+            RemainingDrift: plan.summary().RemainingDrift,
+        });
+        portfolio.apply(&new_amounts_map);
+    }
+    Ok(schedule)
+}
+
+/// Prints a row of new shares per stock for every month.
+pub fn print_schedule(schedule: &[MonthPlan], portfolio: &Portfolio, locale: &Locale) {
+    let mut table = Table::new();
+    let mut titles = vec![Cell::new("Month")];
+    titles.extend(portfolio.Stocks.iter().map(|s| Cell::new(&s.WKN)));
+    titles.extend(["Invested", "Leftover Cash", "Drift"].map(Cell::new));
+    table.set_titles(Row::new(titles));
+
+    for month in schedule {
+        let mut cells = vec![Cell::new(&month.Month.to_string())];
+        cells.extend(portfolio.Stocks.iter().map(|s| {
+            Cell::new(
+                &month
+                    .Orders
+                    .get(&s.WKN)
+                    .map_or(String::new(), |shares| locale.shares(*shares)),
+            )
+        }));
+        cells.push(Cell::new(&locale.money(month.Invested)));
+        cells.push(Cell::new(&locale.money(month.LeftoverCash)));
+        cells.push(Cell::new(&locale.number(month.RemainingDrift, 4)));
+        table.add_row(Row::new(cells));
+    }
+    table.set_format(*format::consts::FORMAT_NO_BORDER);
+
+    println!("\n{table}");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Stock;
+
+    #[test]
+    fn leftover_cash_is_carried_over() {
+        let portfolio = Portfolio {
+            Stocks: vec![Stock {
+                WKN: "A".to_owned(),
+                Price: 30.0,
+                GoalRatio: 1.0,
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let schedule = plan_months(&portfolio, 100.0, 3, &ReinvestOptions::default()).unwrap();
+        let months = schedule
+            .iter()
+            .map(|m| (m.Orders.get("A").copied(), m.LeftoverCash))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            months,
+            [(Some(3.0), 10.0), (Some(3.0), 20.0), (Some(4.0), 0.0)]
+        );
+    }
+}
@@ -0,0 +1,478 @@
+use crate::credentials::config_file;
+use crate::Error;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::time::Instant;
+
+/// Algorithm for choosing which fractional amounts to round up. Ties between
+/// plans with the same score to the cent are broken towards the lowest
+/// remaining drift, then the fewest orders, then more shares of the stock first
+/// by WKN, changing one or two positions at a time. Different solvers may thus
+/// return different plans of the same score.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Solver {
+    /// Depth-first branch-and-bound over whole cents, scales to hundreds of stocks
+    #[default]
+    BranchAndBound,
+    /// Evaluate all 2^n rounding combinations, only feasible up to about 25 stocks
+    Exhaustive,
+    /// Round up the largest remainders while the reinvest amount lasts, i.e. largest remainder
+    /// apportionment, fast but not optimal
+    #[value(alias = "largest-remainder")]
+    Greedy,
+    /// Round to the nearest share, rounding down the smallest remainders while over the reinvest
+    /// amount
+    Nearest,
+}
+
+/// Why a search settled for the best rounding found instead of proving it
+/// optimal
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stop {
+    /// The `--time-limit` passed
+    TimeLimit,
+    /// The search visited `MAX_BRANCHES` branches
+    BranchLimit,
+}
+
+/// What makes one rounding better than another
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Objective {
+    /// Invest as much of the reinvest amount as possible
+    #[default]
+    Invested,
+    /// Minimize the sum of absolute deviations from the goal values
+    L1,
+    /// Minimize the sum of squared deviations from the goal values
+    L2,
+    /// Weigh the invested amount against drift, trades and fees by
+    /// `--objective-weights`
+    Weighted,
+}
+
+/// Weights of the `Weighted` objective, each per unit of money so that a
+/// weight of 1 on every term trades them one for one
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct ObjectiveWeights {
+    /// Weight of the invested amount
+    pub invested: f64,
+    /// Weight of the absolute deviation from the goal value remaining
+    pub drift: f64,
+    /// Cost of every order
+    pub trades: f64,
+    /// Weight of the fees and slippage of an order
+    pub fees: f64,
+}
+
+#[derive(Default, Deserialize)]
+struct Config {
+    #[serde(default)]
+    objectives: HashMap<String, ObjectiveWeights>,
+}
+
+impl ObjectiveWeights {
+    /// Parses comma separated `TERM=WEIGHT` pairs of the terms `invested`,
+    /// `drift`, `trades` and `fees`, e.g. `invested=1,drift=0.5,trades=2`,
+    /// or looks up a preset in `objectives` of the config file. Terms left
+    /// out have a weight of 0.
+    pub fn from_spec(spec: &str) -> Result<Self, Error> {
+        let parse = |spec: &str| -> Option<ObjectiveWeights> {
+            let mut weights = ObjectiveWeights::default();
+            for pair in spec.split(',').map(str::trim) {
+                let (term, weight) = pair.split_once('=')?;
+                let weight = weight.trim().parse::<f64>().ok()?;
+                match term.trim() {
+                    "invested" => weights.invested = weight,
+                    "drift" => weights.drift = weight,
+                    "trades" => weights.trades = weight,
+                    "fees" => weights.fees = weight,
+                    _ => return None,
+                }
+            }
+            Some(weights)
+        };
+        if let Some(weights) = parse(spec) {
+            return Ok(weights);
+        }
+
+        let path = config_file()
+            .filter(|path| path.exists())
+            .ok_or_else(|| simple_error::simple_error!("Invalid objective weights '{}'", spec))?;
+        let config: Config = serde_json::from_reader(File::open(&path)?)
+            .map_err(|err| simple_error::simple_error!("Invalid {}: {}", path.display(), err))?;
+        config.objectives.get(spec).copied().ok_or_else(|| {
+            simple_error::simple_error!(
+                "Invalid objective weights '{}', neither weights like `invested=1,drift=0.5` nor \
+                 a preset in objectives of {}",
+                spec,
+                path.display()
+            )
+            .into()
+        })
+    }
+}
+
+/// Selects the subset of `weights` with the largest sum not exceeding
+/// `capacity`, returning whether each weight is taken.
+///
+/// Weights are tried from the largest to the smallest. A branch is cut off as
+/// soon as taking all remaining weights cannot beat the best sum found so far,
+/// and the search stops once the capacity is filled exactly. After `deadline`
+/// the best subset found so far is returned along with the reason to stop. An
+/// `incumbent` subset within the capacity is the best one until a larger sum
+/// is found.
+pub fn max_subset_sum(
+    weights: &[u64],
+    capacity: u64,
+    deadline: Option<Instant>,
+    incumbent: Option<&[bool]>,
+) -> (Vec<bool>, Option<Stop>) {
+    let mut order = (0..weights.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| weights[b].cmp(&weights[a]));
+    let sorted: Vec<u64> = order.iter().map(|&idx| weights[idx]).collect();
+
+    let mut remaining = vec![0; sorted.len() + 1];
+    for idx in (0..sorted.len()).rev() {
+        remaining[idx] = remaining[idx + 1] + sorted[idx];
+    }
+
+    let mut search = Search {
+        weights: &sorted,
+        remaining: &remaining,
+        capacity,
+        best: 0,
+        best_taken: vec![false; sorted.len()],
+        taken: vec![false; sorted.len()],
+        deadline,
+        branches: 0,
+        stopped: false,
+    };
+    if let Some(incumbent) = incumbent {
+        let taken = order.iter().map(|&idx| incumbent[idx]).collect::<Vec<_>>();
+        let sum = sorted
+            .iter()
+            .zip(taken.iter())
+            .filter(|(_, &t)| t)
+            .map(|(w, _)| w)
+            .sum();
+        if sum <= capacity {
+            search.best = sum;
+            search.best_taken = taken;
+        }
+    }
+    search.branch(0, 0);
+    if search.stopped {
+        log::warn!(
+            "Stopped search at the time limit after {} branches, the result may be up to {} below the optimum",
+            search.branches,
+            capacity - search.best
+        );
+    }
+
+    let mut taken = vec![false; weights.len()];
+    for (&idx, &take) in order.iter().zip(search.best_taken.iter()) {
+        taken[idx] = take;
+    }
+    (taken, search.stopped.then_some(Stop::TimeLimit))
+}
+
+struct Search<'a> {
+    weights: &'a [u64],
+    /// Sum of the weights from an index on
+    remaining: &'a [u64],
+    capacity: u64,
+    best: u64,
+    best_taken: Vec<bool>,
+    taken: Vec<bool>,
+    deadline: Option<Instant>,
+    branches: usize,
+    stopped: bool,
+}
+
+impl Search<'_> {
+    fn branch(&mut self, idx: usize, sum: u64) {
+        self.branches += 1;
+        if sum > self.best {
+            self.best = sum;
+            self.best_taken.clone_from(&self.taken);
+        }
+        self.stopped = self.stopped || past(self.deadline, self.branches);
+        if self.stopped || self.best == self.capacity || idx == self.weights.len() {
+            return;
+        }
+        let upper_bound = sum + self.remaining[idx];
+        if upper_bound <= self.best {
+            return;
+        }
+        if upper_bound <= self.capacity {
+            self.best = upper_bound;
+            self.best_taken.clone_from(&self.taken);
+            self.best_taken[idx..].fill(true);
+            return;
+        }
+
+        if sum + self.weights[idx] <= self.capacity {
+            self.taken[idx] = true;
+            self.branch(idx + 1, sum + self.weights[idx]);
+            self.taken[idx] = false;
+        }
+        self.branch(idx + 1, sum);
+    }
+}
+
+/// Tolerance of cost comparisons against floating point noise
+const EPSILON: f64 = 1e-9;
+
+/// Branches between two looks at the clock
+const DEADLINE_INTERVAL: usize = 1024;
+
+/// Whether `deadline` has passed, looking at the clock only every
+/// `DEADLINE_INTERVAL` steps
+pub fn past(deadline: Option<Instant>, steps: usize) -> bool {
+    steps.is_multiple_of(DEADLINE_INTERVAL)
+        && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+}
+
+/// Branches after which [`best_choices`] settles for the best choices found
+pub const MAX_BRANCHES: usize = 1_000_000;
+
+/// Candidate quantity of a position for [`best_choices`]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Choice {
+    /// Cash needed, negative for the proceeds of a sale
+    pub cost: f64,
+    /// Contribution to the objective, higher is better
+    pub score: f64,
+}
+
+/// Picks one choice per item maximizing the total score with a total cost
+/// within `budget`, returning the index of the choice per item or `None` if no
+/// combination fits. The total score is at most `tolerance` below the optimum.
+///
+/// Depth-first branch-and-bound over the items in the order of their score per
+/// cost. A branch is cut off when even the linear relaxation of the remaining
+/// items, which may take fractions of choices, cannot beat the best total
+/// found so far. After `MAX_BRANCHES` or `deadline` the best choices found so
+/// far are returned along with the reason to stop. The `incumbent` choices, if
+/// within the budget, are the best ones until better ones are found.
+pub fn best_choices(
+    items: &[Vec<Choice>],
+    budget: f64,
+    tolerance: f64,
+    deadline: Option<Instant>,
+    incumbent: Option<&[usize]>,
+) -> (Option<Vec<usize>>, Option<Stop>) {
+    // Cheapest choice of each item, the one with the best score among equally
+    // cheap ones
+    let cheapest: Vec<Choice> = items
+        .iter()
+        .map(|choices| {
+            *choices
+                .iter()
+                .min_by(|a, b| a.cost.total_cmp(&b.cost).then(b.score.total_cmp(&a.score)))
+                .expect("every item has a choice")
+        })
+        .collect();
+    let hulls: Vec<Vec<Upgrade>> = items
+        .iter()
+        .zip(cheapest.iter())
+        .map(|(choices, cheapest)| hull_upgrades(choices, *cheapest))
+        .collect();
+
+    // Deciding the items in the order the linear relaxation takes them finds
+    // good choices early
+    let efficiency = |idx: usize| hulls[idx].first().map_or(0.0, |u| u.score / u.cost);
+    let mut order = (0..items.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| efficiency(b).total_cmp(&efficiency(a)));
+    let sorted: Vec<&Vec<Choice>> = order.iter().map(|&idx| &items[idx]).collect();
+
+    let mut min_cost = vec![0.0; sorted.len() + 1];
+    let mut cheapest_score = vec![0.0; sorted.len() + 1];
+    for pos in (0..sorted.len()).rev() {
+        min_cost[pos] = min_cost[pos + 1] + cheapest[order[pos]].cost;
+        cheapest_score[pos] = cheapest_score[pos + 1] + cheapest[order[pos]].score;
+    }
+
+    let mut upgrades: Vec<Upgrade> = order
+        .iter()
+        .enumerate()
+        .flat_map(|(pos, &idx)| hulls[idx].iter().map(move |u| Upgrade { pos, ..*u }))
+        .collect();
+    upgrades.sort_by(|a, b| (b.score / b.cost).total_cmp(&(a.score / a.cost)));
+
+    let candidates = sorted
+        .iter()
+        .map(|choices| {
+            let mut candidates = (0..choices.len()).collect::<Vec<_>>();
+            candidates.sort_by(|&a, &b| choices[b].score.total_cmp(&choices[a].score));
+            candidates
+        })
+        .collect();
+
+    let mut search = ChoiceSearch {
+        items: &sorted,
+        candidates,
+        min_cost,
+        cheapest_score,
+        upgrades,
+        budget,
+        tolerance: tolerance.max(EPSILON),
+        deadline,
+        branches: 0,
+        stopped: false,
+        best: f64::NEG_INFINITY,
+        best_chosen: None,
+        chosen: vec![0; sorted.len()],
+    };
+    if let Some(incumbent) = incumbent {
+        let chosen: Vec<usize> = order.iter().map(|&idx| incumbent[idx]).collect();
+        let (cost, score) = sorted
+            .iter()
+            .zip(chosen.iter())
+            .fold((0.0, 0.0), |(cost, score), (choices, &c)| {
+                (cost + choices[c].cost, score + choices[c].score)
+            });
+        if cost <= budget + EPSILON {
+            search.best = score;
+            search.best_chosen = Some(chosen);
+        }
+    }
+    search.branch(0, 0.0, 0.0);
+    if search.stopped {
+        log::warn!(
+            "Stopped search after {} branches, the result may be up to {:.2} below the optimum",
+            search.branches,
+            search.relaxed_bound(0, budget) - search.best
+        );
+    }
+
+    let stop = match search.branches >= MAX_BRANCHES {
+        true => Stop::BranchLimit,
+        false => Stop::TimeLimit,
+    };
+    let chosen = search.best_chosen.map(|best_chosen| {
+        let mut chosen = vec![0; items.len()];
+        for (&idx, &choice) in order.iter().zip(best_chosen.iter()) {
+            chosen[idx] = choice;
+        }
+        chosen
+    });
+    (chosen, search.stopped.then_some(stop))
+}
+
+/// Step between two neighbouring choices on the upper convex hull of an item
+#[derive(Clone, Copy)]
+struct Upgrade {
+    /// Position of the item in the search order
+    pos: usize,
+    cost: f64,
+    score: f64,
+}
+
+/// Steps from the cheapest choice of an item along the upper convex hull of
+/// its choices, with decreasing score per cost.
+fn hull_upgrades(choices: &[Choice], cheapest: Choice) -> Vec<Upgrade> {
+    let mut points = choices
+        .iter()
+        .filter(|c| c.score > cheapest.score)
+        .copied()
+        .collect::<Vec<_>>();
+    points.sort_by(|a, b| a.cost.total_cmp(&b.cost).then(b.score.total_cmp(&a.score)));
+
+    let mut hull = vec![cheapest];
+    for point in points {
+        if point.score <= hull[hull.len() - 1].score {
+            continue;
+        }
+        // Drop the last point while it lies on or below the line from the
+        // one before to the new point
+        while hull.len() >= 2 {
+            let (a, b) = (hull[hull.len() - 2], hull[hull.len() - 1]);
+            match (b.score - a.score) / (b.cost - a.cost)
+                <= (point.score - a.score) / (point.cost - a.cost)
+            {
+                true => hull.pop(),
+                false => break,
+            };
+        }
+        hull.push(point);
+    }
+
+    hull.windows(2)
+        .map(|w| Upgrade {
+            pos: 0,
+            cost: w[1].cost - w[0].cost,
+            score: w[1].score - w[0].score,
+        })
+        .collect()
+}
+
+struct ChoiceSearch<'a> {
+    items: &'a [&'a Vec<Choice>],
+    /// Choice indices of each item by decreasing score
+    candidates: Vec<Vec<usize>>,
+    /// Sum of the cheapest costs from a position on
+    min_cost: Vec<f64>,
+    /// Sum of the scores of the cheapest choices from a position on
+    cheapest_score: Vec<f64>,
+    /// Hull steps of all items by decreasing score per cost
+    upgrades: Vec<Upgrade>,
+    budget: f64,
+    tolerance: f64,
+    deadline: Option<Instant>,
+    branches: usize,
+    /// Whether `MAX_BRANCHES` or the deadline was reached
+    stopped: bool,
+    best: f64,
+    best_chosen: Option<Vec<usize>>,
+    chosen: Vec<usize>,
+}
+
+impl ChoiceSearch<'_> {
+    fn branch(&mut self, pos: usize, cost: f64, score: f64) {
+        self.branches += 1;
+        if pos == self.items.len() {
+            if score > self.best {
+                self.best = score;
+                self.best_chosen = Some(self.chosen.clone());
+            }
+            return;
+        }
+        self.stopped =
+            self.stopped || self.branches >= MAX_BRANCHES || past(self.deadline, self.branches);
+        if self.stopped
+            || score + self.relaxed_bound(pos, self.budget - cost) <= self.best + self.tolerance
+        {
+            return;
+        }
+
+        for idx in 0..self.candidates[pos].len() {
+            let choice_idx = self.candidates[pos][idx];
+            let choice = self.items[pos][choice_idx];
+            if cost + choice.cost + self.min_cost[pos + 1] > self.budget + EPSILON {
+                continue;
+            }
+            self.chosen[pos] = choice_idx;
+            self.branch(pos + 1, cost + choice.cost, score + choice.score);
+        }
+    }
+
+    /// Best score of the items from `pos` on if fractions of choices were
+    /// allowed
+    fn relaxed_bound(&self, pos: usize, budget: f64) -> f64 {
+        let mut left = budget - self.min_cost[pos];
+        let mut bound = self.cheapest_score[pos];
+        for upgrade in self.upgrades.iter().filter(|u| u.pos >= pos) {
+            if upgrade.cost <= left {
+                left -= upgrade.cost;
+                bound += upgrade.score;
+            } else {
+                bound += upgrade.score * left / upgrade.cost;
+                break;
+            }
+        }
+        bound
+    }
+}
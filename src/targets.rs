@@ -0,0 +1,123 @@
+use crate::import::{lookup_goal_ratio, GoalRatios};
+use crate::{Error, Portfolio};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+/// Target allocation by name, e.g. asset class or region, down to the stocks
+/// keyed by ISIN, WKN or symbol
+pub type Targets = BTreeMap<String, Target>;
+
+/// Node of a target allocation tree
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+pub struct Target {
+    /// Ratio within the parent node
+    #[serde(alias = "ratio")]
+    pub Ratio: f64,
+    /// Split of the node, a stock if empty
+    #[serde(default, alias = "children")]
+    pub Children: Targets,
+}
+
+pub fn read_targets<R: Read>(reader: R) -> Result<Targets, Error> {
+    Ok(serde_json::from_reader(reader)?)
+}
+
+/// Goal ratios of the stocks as the product of the ratios on the path to
+/// them. The ratios of the children of a node have to sum up to 1, whereas the
+/// top level is left to the ratio policy.
+pub fn flatten_targets(targets: &Targets) -> Result<GoalRatios, Error> {
+    let mut goal_ratios = GoalRatios::new();
+    flatten_into(targets, 1.0, &mut goal_ratios)?;
+    Ok(goal_ratios)
+}
+
+fn flatten_into(targets: &Targets, ratio: f64, goal_ratios: &mut GoalRatios) -> Result<(), Error> {
+    for (name, target) in targets {
+        if target.Children.is_empty() {
+            if goal_ratios
+                .insert(name.clone(), ratio * target.Ratio)
+                .is_some()
+            {
+                simple_error::bail!("{} appears more than once in the targets", name);
+            }
+            continue;
+        }
+        let ratio_sum: f64 = target.Children.values().map(|t| t.Ratio).sum();
+        if (ratio_sum - 1.0).abs() > 1e-6 {
+            simple_error::bail!("Ratios below {} sum up to {} instead of 1", name, ratio_sum);
+        }
+        flatten_into(&target.Children, ratio * target.Ratio, goal_ratios)?;
+    }
+    Ok(())
+}
+
+/// Sets the goal ratios of the stocks in `portfolio` from the flattened
+/// `targets`. Fails on stocks missing from the targets and on targets matching
+/// no stock.
+pub fn apply_targets(portfolio: &mut Portfolio, targets: &Targets) -> Result<(), Error> {
+    let goal_ratios = flatten_targets(targets)?;
+    for stock in portfolio.Stocks.iter_mut() {
+        stock.GoalRatio =
+            lookup_goal_ratio(&goal_ratios, &[&stock.ISIN, &stock.WKN, &stock.Symbol])?;
+    }
+    if let Some(name) = goal_ratios.keys().find(|name| {
+        !portfolio
+            .Stocks
+            .iter()
+            .any(|s| [&s.ISIN, &s.WKN, &s.Symbol].contains(name))
+    }) {
+        simple_error::bail!("No stock for {} of the targets", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(json: &str) -> Targets {
+        read_targets(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn goal_ratios_are_products_along_the_path() {
+        let goal_ratios = flatten_targets(&targets(
+            r#"{
+                "Equities": {"Ratio": 0.8, "Children": {
+                    "World": {"Ratio": 0.75},
+                    "EM": {"Ratio": 0.25}
+                }},
+                "Bonds": {"Ratio": 0.2}
+            }"#,
+        ))
+        .unwrap();
+        assert!((goal_ratios["World"] - 0.6).abs() < 1e-9);
+        assert!((goal_ratios["EM"] - 0.2).abs() < 1e-9);
+        assert!((goal_ratios["Bonds"] - 0.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn sibling_ratios_have_to_sum_up_to_one() {
+        let err = flatten_targets(&targets(
+            r#"{"Equities": {"Ratio": 1, "Children": {
+                "World": {"Ratio": 0.7},
+                "EM": {"Ratio": 0.2}
+            }}}"#,
+        ))
+        .unwrap_err();
+        assert!(err.to_string().contains("Equities"), "{err}");
+    }
+
+    #[test]
+    fn duplicate_stocks_are_an_error() {
+        assert!(flatten_targets(&targets(
+            r#"{
+                "A": {"Ratio": 0.5, "Children": {"World": {"Ratio": 1}}},
+                "B": {"Ratio": 0.5, "Children": {"World": {"Ratio": 1}}}
+            }"#,
+        ))
+        .is_err());
+    }
+}
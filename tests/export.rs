@@ -0,0 +1,70 @@
+use rebalancing::plan::Plan;
+use rebalancing::{Portfolio, Stock};
+use std::collections::HashMap;
+
+/// Portfolio with a stock traded in USD at 110 and one in EUR, and the same
+/// portfolio converted to EUR at 1.10 USD per EUR
+fn portfolios() -> (Portfolio, Portfolio) {
+    let native = Portfolio {
+        Stocks: vec![
+            Stock {
+                WKN: "A".to_owned(),
+                Symbol: "A".to_owned(),
+                Price: 50.0,
+                Shares: 10.0,
+                GoalRatio: 1.0,
+                ..Default::default()
+            },
+            Stock {
+                WKN: "B".to_owned(),
+                Symbol: "B".to_owned(),
+                Price: 110.0,
+                Shares: 5.0,
+                GoalRatio: 1.0,
+                Currency: "USD".to_owned(),
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    let mut converted = native.clone();
+    converted.Stocks[1].Price = 110.0 / 1.1;
+    converted.Stocks[1].Currency = "EUR".to_owned();
+    (native, converted)
+}
+
+fn plan() -> Plan {
+    let (native, converted) = portfolios();
+    let new_amounts = HashMap::from([("A".to_owned(), -2.0), ("B".to_owned(), 6.0)]);
+    let mut plan = Plan::new(&converted, &new_amounts, 500.0, 600.0);
+    plan.apply_native_prices(&native, "EUR");
+    plan
+}
+
+#[test]
+fn order_limits_are_native_prices() {
+    let mut csv = Vec::new();
+    plan().to_order_csv_writer(&mut csv, "EUR").unwrap();
+    assert_eq!(
+        String::from_utf8(csv).unwrap(),
+        "symbol,side,quantity,limit,currency\n\
+         A,SELL,2.0,50.0,EUR\n\
+         B,BUY,6.0,110.0,USD\n"
+    );
+}
+
+#[test]
+fn portfolio_performance_values_are_native() {
+    let mut csv = Vec::new();
+    plan()
+        .to_portfolio_performance_writer(&mut csv, "2024-01-02", "EUR")
+        .unwrap();
+    let csv = String::from_utf8(csv).unwrap();
+    let mut lines = csv.lines();
+    assert_eq!(
+        lines.next(),
+        Some("Date;Type;ISIN;WKN;Ticker Symbol;Shares;Value;Transaction Currency")
+    );
+    assert_eq!(lines.next(), Some("2024-01-02;Sell;;A;A;2.0;100.00;EUR"));
+    assert_eq!(lines.next(), Some("2024-01-02;Buy;;B;B;6.0;660.00;USD"));
+}
@@ -0,0 +1,61 @@
+use rebalancing::fx::{self, FxRates};
+use rebalancing::Portfolio;
+use std::collections::BTreeMap;
+
+fn rates() -> FxRates {
+    FxRates::from([("EUR".to_owned(), 1.0), ("USD".to_owned(), 1.1)])
+}
+
+fn portfolio(cash: &[(&str, f64)]) -> Portfolio {
+    Portfolio {
+        Cash: cash.iter().map(|(c, a)| (c.to_string(), *a)).collect(),
+        ..Default::default()
+    }
+}
+
+fn cash(portfolio: &Portfolio) -> BTreeMap<&str, f64> {
+    portfolio
+        .Cash
+        .iter()
+        .map(|(currency, amount)| (currency.as_str(), *amount))
+        .collect()
+}
+
+#[test]
+fn spending_round_trips_multi_currency_cash() {
+    let native = portfolio(&[("EUR", 50.0), ("USD", 5500.0)]);
+    let mut converted = native.clone();
+    fx::convert_to_base(&mut converted, "EUR", &rates()).unwrap();
+    assert_eq!(cash(&converted), BTreeMap::from([("EUR", 5050.0)]));
+
+    let mut updated = native.clone();
+    fx::withdraw_in_base(&mut updated, "EUR", 1000.0, &rates()).unwrap();
+    // The EUR balance is spent first, the remaining 950 EUR are 1045 USD
+    assert_eq!(
+        cash(&updated),
+        BTreeMap::from([("EUR", 0.0), ("USD", 4455.0)])
+    );
+
+    fx::convert_to_base(&mut updated, "EUR", &rates()).unwrap();
+    assert!((updated.free_cash() - (converted.free_cash() - 1000.0)).abs() < 1e-9);
+}
+
+#[test]
+fn proceeds_are_added_in_base_currency() {
+    let mut updated = portfolio(&[("USD", 110.0)]);
+    fx::withdraw_in_base(&mut updated, "EUR", -20.0, &rates()).unwrap();
+    assert_eq!(
+        cash(&updated),
+        BTreeMap::from([("EUR", 20.0), ("USD", 110.0)])
+    );
+}
+
+#[test]
+fn overspending_leaves_a_negative_base_balance() {
+    let mut updated = portfolio(&[("EUR", 10.0), ("USD", 110.0)]);
+    fx::withdraw_in_base(&mut updated, "EUR", 120.0, &rates()).unwrap();
+    assert_eq!(
+        cash(&updated),
+        BTreeMap::from([("EUR", -10.0), ("USD", 0.0)])
+    );
+}
@@ -0,0 +1,154 @@
+use rebalancing::import::{self, GoalRatios, UsBroker};
+use rebalancing::{Lot, Portfolio, Stock};
+use std::fs::File;
+
+fn fixture(name: &str) -> File {
+    File::open(format!(
+        "{}/tests/fixtures/{name}",
+        env!("CARGO_MANIFEST_DIR")
+    ))
+    .unwrap()
+}
+
+fn goal_ratios(ids: &[&str]) -> GoalRatios {
+    ids.iter().map(|id| (id.to_string(), 1.0)).collect()
+}
+
+fn stock<'a>(portfolio: &'a Portfolio, wkn: &str) -> &'a Stock {
+    portfolio
+        .Stocks
+        .iter()
+        .find(|s| s.WKN == wkn)
+        .unwrap_or_else(|| panic!("{wkn} missing in {:?}", portfolio.Stocks))
+}
+
+fn assert_close(actual: f64, expected: f64) {
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "{actual} is not {expected}"
+    );
+}
+
+#[test]
+fn dkb() {
+    let portfolio =
+        import::read_dkb(fixture("dkb.csv"), &goal_ratios(&["A0RPWH", "A111X9"])).unwrap();
+    assert_eq!(portfolio.Stocks.len(), 2);
+    let world = stock(&portfolio, "A0RPWH");
+    assert_eq!(world.ISIN, "IE00B4L5Y983");
+    assert_close(world.Shares, 1250.5);
+    assert_close(world.Price, 82.34);
+}
+
+#[test]
+fn comdirect() {
+    let portfolio = import::read_comdirect(
+        fixture("comdirect.csv"),
+        &goal_ratios(&["A0RPWH", "A111X9"]),
+    )
+    .unwrap();
+    assert_eq!(portfolio.Stocks.len(), 2);
+    assert_close(stock(&portfolio, "A0RPWH").Shares, 12.5);
+    assert_close(stock(&portfolio, "A111X9").Shares, 1040.0);
+    assert_close(stock(&portfolio, "A111X9").Price, 31.5);
+}
+
+#[test]
+fn degiro_english_has_decimal_points() {
+    let goal_ratios = goal_ratios(&["IE00B3RBWM25", "US0378331005"]);
+    let portfolio = import::read_degiro(fixture("degiro_en.csv"), &goal_ratios).unwrap();
+    assert_eq!(portfolio.Stocks.len(), 2);
+    let all_world = stock(&portfolio, "IE00B3RBWM25");
+    assert_close(all_world.Shares, 1500.0);
+    assert_close(all_world.Price, 100.0);
+    // Prices are taken from the value in EUR
+    assert_close(stock(&portfolio, "US0378331005").Price, 165.0);
+}
+
+#[test]
+fn degiro_german_has_decimal_commas_and_skips_closed_positions() {
+    let goal_ratios = goal_ratios(&["IE00B3RBWM25", "IE00B4L5Y983"]);
+    let portfolio = import::read_degiro(fixture("degiro_de.csv"), &goal_ratios).unwrap();
+    assert_eq!(portfolio.Stocks.len(), 1);
+    let all_world = stock(&portfolio, "IE00B3RBWM25");
+    assert_close(all_world.Shares, 1500.0);
+    assert_close(all_world.Price, 100.0);
+}
+
+#[test]
+fn us_broker_skips_cash_and_disclaimers() {
+    let portfolio = import::read_us_broker(
+        fixture("fidelity.csv"),
+        UsBroker::Fidelity,
+        &goal_ratios(&["VTI", "VXUS"]),
+    )
+    .unwrap();
+    assert_eq!(portfolio.Stocks.len(), 2);
+    let vti = stock(&portfolio, "VTI");
+    assert_eq!(vti.Symbol, "VTI");
+    assert_close(vti.Shares, 1200.0);
+    assert_close(vti.Price, 240.5);
+}
+
+#[test]
+fn ibkr_flex_converts_to_base_currency_and_skips_lots() {
+    let portfolio =
+        import::read_ibkr_flex(fixture("ibkr_flex.xml"), &goal_ratios(&["12345", "VWCE"])).unwrap();
+    assert_eq!(portfolio.Stocks.len(), 2);
+    let vti = stock(&portfolio, "VTI");
+    assert_eq!(vti.ISIN, "US9229087690");
+    assert_close(vti.Shares, 10.0);
+    assert_close(vti.Price, 216.0);
+    assert_close(stock(&portfolio, "VWCE").Price, 110.5);
+}
+
+#[test]
+fn ghostfolio_sums_activities() {
+    let portfolio =
+        import::read_ghostfolio(fixture("ghostfolio.json"), &goal_ratios(&["VWCE.DE"])).unwrap();
+    // BTC was sold completely
+    assert_eq!(portfolio.Stocks.len(), 1);
+    let vwce = stock(&portfolio, "VWCE.DE");
+    assert_close(vwce.Shares, 6.0);
+    assert_close(vwce.Price, 101.0);
+}
+
+#[test]
+fn beancount_lots_from_costs() {
+    let portfolio =
+        import::read_ledger(fixture("journal.beancount"), &goal_ratios(&["VWCE"])).unwrap();
+    let vwce = stock(&portfolio, "VWCE");
+    assert_close(vwce.Shares, 3.0);
+    assert_close(vwce.Price, 112.5);
+    // The sale took all of the oldest lot and 2 shares of the next one
+    assert_eq!(
+        vwce.Lots,
+        vec![Lot {
+            Date: "2023-06-10".to_owned(),
+            Shares: 3.0,
+            Price: 100.0,
+        }]
+    );
+}
+
+#[test]
+fn hledger_lots_from_prices_with_decimal_comma() {
+    let portfolio =
+        import::read_ledger(fixture("journal.hledger"), &goal_ratios(&["AAPL"])).unwrap();
+    let aapl = stock(&portfolio, "AAPL");
+    assert_close(aapl.Shares, 3.0);
+    assert_close(aapl.Price, 180.25);
+    assert_eq!(
+        aapl.Lots,
+        vec![Lot {
+            Date: "2023-03-10".to_owned(),
+            Shares: 3.0,
+            Price: 160.5,
+        }]
+    );
+}
+
+#[test]
+fn missing_goal_ratio_is_an_error() {
+    assert!(import::read_dkb(fixture("dkb.csv"), &goal_ratios(&["A0RPWH"])).is_err());
+}
@@ -0,0 +1,50 @@
+use rebalancing::{CsvDialect, Portfolio, Stock};
+
+fn portfolio() -> Portfolio {
+    Portfolio {
+        Stocks: vec![
+            Stock {
+                WKN: "A".to_owned(),
+                Price: 95.5,
+                Shares: 1.0,
+                GoalRatio: 0.5,
+                ..Default::default()
+            },
+            Stock {
+                WKN: "B".to_owned(),
+                Price: 99.0,
+                Shares: 2.0,
+                GoalRatio: 0.5,
+                Band: Some(5.0),
+                Alternative: Some("A".to_owned()),
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn json_leaves_out_unset_fields() {
+    let json = serde_json::to_string(&portfolio()).unwrap();
+    assert!(!json.contains("null"), "{json}");
+    assert!(json.contains(r#""Band":5.0"#), "{json}");
+}
+
+#[test]
+fn csv_round_trips_fields_set_for_some_stocks() {
+    let dialect = CsvDialect {
+        delimiter: b';',
+        decimal_comma: true,
+    };
+    let mut csv = Vec::new();
+    portfolio()
+        .to_csv_writer_with_dialect(&mut csv, &dialect)
+        .unwrap();
+    let read = Portfolio::from_csv_reader_with_dialect(csv.as_slice(), &dialect).unwrap();
+    assert_eq!(read.Stocks[0].Band, None);
+    assert_eq!(read.Stocks[0].Alternative, None);
+    assert_eq!(read.Stocks[1].Band, Some(5.0));
+    assert_eq!(read.Stocks[1].Alternative.as_deref(), Some("A"));
+    assert_eq!(read.Stocks[1].Price, 99.0);
+}
@@ -0,0 +1,212 @@
+use rebalancing::fees::FeeModel;
+use rebalancing::money::to_cents;
+use rebalancing::solver::{Objective, ObjectiveWeights, Solver};
+use rebalancing::{calculate_optimal_reinvest, Portfolio, ReinvestOptions, Stock};
+use std::collections::HashMap;
+
+/// Deterministic xorshift generator, so failures can be reproduced by seed
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    /// Amount in whole cents between `low` and `high`
+    fn amount(&mut self, low: f64, high: f64) -> f64 {
+        let cents = self.below(((high - low) * 100.0) as u64);
+        low + cents as f64 / 100.0
+    }
+}
+
+fn random_portfolio(rng: &mut Rng) -> Portfolio {
+    let stocks = 2 + rng.below(9);
+    Portfolio {
+        Stocks: (0..stocks)
+            .map(|idx| Stock {
+                WKN: format!("WKN{idx}"),
+                Price: rng.amount(5.0, 500.0),
+                Shares: rng.below(40) as f64,
+                GoalRatio: 1.0 + rng.below(20) as f64,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn random_options(rng: &mut Rng) -> ReinvestOptions {
+    ReinvestOptions {
+        no_selling: rng.below(2) == 0,
+        objective: [
+            Objective::Invested,
+            Objective::L1,
+            Objective::L2,
+            Objective::Weighted,
+        ][rng.below(4) as usize],
+        weights: ObjectiveWeights {
+            invested: [0.0, 1.0][rng.below(2) as usize],
+            drift: [0.0, 0.5, 1.0][rng.below(3) as usize],
+            trades: [0.0, 2.0][rng.below(2) as usize],
+            fees: 1.0,
+        },
+        trade_penalty: [0.0, 0.0, 5.0][rng.below(3) as usize],
+        fees: [
+            FeeModel::default(),
+            FeeModel {
+                fixed: 1.0,
+                rate: 0.0025,
+            },
+        ][rng.below(2) as usize],
+        ..Default::default()
+    }
+}
+
+/// Value of the objective of `options` for the `new_amounts`, minus the trade
+/// penalty of every order, in whole cents per stock like the solvers score
+fn objective(
+    portfolio: &Portfolio,
+    reinvest: f64,
+    options: &ReinvestOptions,
+    new_amounts: &HashMap<String, f64>,
+) -> i64 {
+    let goal_total: f64 = portfolio
+        .Stocks
+        .iter()
+        .map(|s| s.Price * s.Shares)
+        .sum::<f64>()
+        + reinvest;
+    let ratios: f64 = portfolio.Stocks.iter().map(|s| s.GoalRatio).sum();
+    portfolio
+        .Stocks
+        .iter()
+        .map(|stock| {
+            let new_amount = new_amounts.get(&stock.WKN).copied().unwrap_or(0.0);
+            let order_value = new_amount * stock.Price;
+            let deviation =
+                stock.Price * stock.Shares + order_value - stock.GoalRatio / ratios * goal_total;
+            let penalty = match new_amount == 0.0 {
+                true => 0.0,
+                false => options.trade_penalty,
+            };
+            let score = match options.objective {
+                Objective::Invested => order_value,
+                Objective::L1 => -deviation.abs(),
+                Objective::L2 => -deviation * deviation / goal_total,
+                Objective::Weighted => {
+                    let weights = options.weights;
+                    weights.invested * order_value
+                        - weights.drift * deviation.abs()
+                        - weights.trades * f64::from(u8::from(new_amount != 0.0))
+                        - weights.fees
+                            * (options.fees.fee(order_value) + stock.slippage(order_value))
+                }
+            };
+            to_cents(score - penalty)
+        })
+        .sum()
+}
+
+#[test]
+fn branch_and_bound_matches_exhaustive_search() {
+    for seed in 1..=500_u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+        let portfolio = random_portfolio(&mut rng);
+        let reinvest = rng.amount(50.0, 5000.0);
+        let options = random_options(&mut rng);
+
+        let solve = |solver| {
+            calculate_optimal_reinvest(&portfolio, reinvest, &ReinvestOptions { solver, ..options })
+        };
+        match (solve(Solver::BranchAndBound), solve(Solver::Exhaustive)) {
+            (Ok((_, new_amounts, stop)), Ok((_, expected, _))) => {
+                // Plans may differ in ties only
+                assert_eq!(stop, None, "seed {seed}");
+                assert_eq!(
+                    objective(&portfolio, reinvest, &options, &new_amounts),
+                    objective(&portfolio, reinvest, &options, &expected),
+                    "seed {seed}: {new_amounts:?} != {expected:?}"
+                );
+            }
+            (Err(_), Err(_)) => {}
+            (bnb, exhaustive) => panic!("seed {seed}: {bnb:?} != {exhaustive:?}"),
+        }
+    }
+}
+
+#[test]
+fn rounding_stays_within_the_reinvest_amount() {
+    for seed in 1..=500_u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x2545_F491_4F6C_DD1D));
+        let portfolio = random_portfolio(&mut rng);
+        let reinvest = rng.amount(50.0, 5000.0);
+        let options = random_options(&mut rng);
+
+        let Ok((invested, new_amounts, _)) =
+            calculate_optimal_reinvest(&portfolio, reinvest, &options)
+        else {
+            continue;
+        };
+        assert!(invested <= reinvest + 1e-9, "seed {seed}");
+        for (wkn, new_amount) in new_amounts.iter() {
+            assert_eq!(new_amount.fract(), 0.0, "seed {seed}: {wkn}");
+            assert!(!options.no_selling || *new_amount >= 0.0, "seed {seed}");
+        }
+    }
+}
+
+#[test]
+fn maximum_order_value_keeps_the_rounding_feasible() {
+    for seed in 1..=100_u64 {
+        let mut rng = Rng(seed.wrapping_mul(0x5851_F42D_4C95_7F2D));
+        let mut portfolio = random_portfolio(&mut rng);
+        for stock in portfolio.Stocks.iter_mut() {
+            stock.Shares = 10.0 + rng.below(40) as f64;
+        }
+        let options = ReinvestOptions {
+            max_order: Some(800.0),
+            ..Default::default()
+        };
+
+        let (invested, new_amounts, _) = calculate_optimal_reinvest(&portfolio, 1500.0, &options)
+            .unwrap_or_else(|err| panic!("seed {seed}: {err}"));
+        assert!(invested <= 1500.0, "seed {seed}");
+        for stock in portfolio.Stocks.iter() {
+            let order_value = new_amounts.get(&stock.WKN).copied().unwrap_or(0.0) * stock.Price;
+            assert!(
+                order_value.abs() <= 800.0 + 1e-9,
+                "seed {seed}: {}",
+                stock.WKN
+            );
+        }
+    }
+}
+
+#[test]
+fn exhaustive_search_of_too_many_combinations_is_an_error() {
+    let portfolio = Portfolio {
+        Stocks: (0..100)
+            .map(|idx| Stock {
+                WKN: format!("WKN{idx}"),
+                Price: 7.0 + idx as f64 / 10.0,
+                Shares: 10.0,
+                GoalRatio: 1.0,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let options = ReinvestOptions {
+        solver: Solver::Exhaustive,
+        ..Default::default()
+    };
+    let err = calculate_optimal_reinvest(&portfolio, 12345.67, &options).unwrap_err();
+    assert!(err.to_string().contains("branch-and-bound"), "{err}");
+}